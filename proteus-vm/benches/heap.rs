@@ -0,0 +1,156 @@
+//! Compares the heap with the best fit allocator it replaced, which scanned an unsorted free list
+//! on every allocation and sorted it on every free.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use proteus_vm::memory::heap::Heap;
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+struct BestFitHeap {
+    free_list: Vec<(usize, usize)>,
+}
+
+impl BestFitHeap {
+    fn new(size: usize) -> Self {
+        Self { free_list: vec![(0, size)] }
+    }
+
+    fn allocate(&mut self, size: usize) -> Result<usize, String> {
+        let index = self
+            .free_list
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, block_size))| *block_size >= size)
+            .min_by_key(|(_, (_, block_size))| *block_size)
+            .map(|(index, _)| index)
+            .ok_or("Out of memory")?;
+        let (start, block_size) = self.free_list.remove(index);
+        if block_size > size {
+            self.free_list.push((start + size, block_size - size));
+        }
+        Ok(start)
+    }
+
+    fn free(&mut self, start: usize, size: usize) {
+        self.free_list.push((start, size));
+        self.free_list.sort_by_key(|(start, _)| *start);
+        let mut index = 0;
+        while index < self.free_list.len() - 1 {
+            let (current_start, current_size) = self.free_list[index];
+            let (next_start, next_size) = self.free_list[index + 1];
+            if current_start + current_size == next_start {
+                self.free_list[index].1 += next_size;
+                self.free_list.remove(index + 1);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+trait Allocator {
+    fn allocate(&mut self, size: usize) -> usize;
+    fn free(&mut self, start: usize, size: usize);
+}
+
+impl Allocator for Heap {
+    fn allocate(&mut self, size: usize) -> usize {
+        Heap::allocate(self, size).unwrap()
+    }
+
+    fn free(&mut self, start: usize, size: usize) {
+        Heap::free(self, start, size).unwrap()
+    }
+}
+
+impl Allocator for BestFitHeap {
+    fn allocate(&mut self, size: usize) -> usize {
+        BestFitHeap::allocate(self, size).unwrap()
+    }
+
+    fn free(&mut self, start: usize, size: usize) {
+        BestFitHeap::free(self, start, size)
+    }
+}
+
+/// A deterministic sequence of sizes and indices.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % bound
+    }
+}
+
+/// Allocates `count` small objects and frees them in random order.
+fn fill_and_drain(allocator: &mut impl Allocator, count: usize) {
+    let mut random = Lcg(1);
+    let mut live: Vec<(usize, usize)> = (0..count)
+        .map(|_| {
+            let size = 8 + random.next(56);
+            (allocator.allocate(size), size)
+        })
+        .collect();
+    while !live.is_empty() {
+        let (start, size) = live.swap_remove(random.next(live.len()));
+        allocator.free(black_box(start), size);
+    }
+}
+
+/// Mostly small objects with the occasional large one.
+fn size(random: &mut Lcg) -> usize {
+    if random.next(10) == 0 {
+        256 + random.next(4096)
+    } else {
+        8 + random.next(120)
+    }
+}
+
+/// Keeps about `count` objects of mixed sizes alive while replacing random ones.
+fn churn(allocator: &mut impl Allocator, count: usize) {
+    let mut random = Lcg(2);
+    let mut live: Vec<(usize, usize)> = (0..count)
+        .map(|_| {
+            let size = size(&mut random);
+            (allocator.allocate(size), size)
+        })
+        .collect();
+    for _ in 0..count * 4 {
+        let index = random.next(live.len());
+        let (start, old_size) = live[index];
+        allocator.free(start, old_size);
+        let new_size = size(&mut random);
+        live[index] = (allocator.allocate(new_size), new_size);
+    }
+    for (start, size) in live {
+        allocator.free(start, size);
+    }
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill_and_drain");
+    for count in [100, 1000, 5000] {
+        group.bench_with_input(BenchmarkId::new("segregated", count), &count, |b, &count| {
+            b.iter_batched_ref(|| Heap::new(HEAP_SIZE), |heap| fill_and_drain(heap, count), BatchSize::LargeInput)
+        });
+        group.bench_with_input(BenchmarkId::new("best_fit", count), &count, |b, &count| {
+            b.iter_batched_ref(|| BestFitHeap::new(HEAP_SIZE), |heap| fill_and_drain(heap, count), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("churn");
+    for count in [100, 1000, 5000] {
+        group.bench_with_input(BenchmarkId::new("segregated", count), &count, |b, &count| {
+            b.iter_batched_ref(|| Heap::new(HEAP_SIZE), |heap| churn(heap, count), BatchSize::LargeInput)
+        });
+        group.bench_with_input(BenchmarkId::new("best_fit", count), &count, |b, &count| {
+            b.iter_batched_ref(|| BestFitHeap::new(HEAP_SIZE), |heap| churn(heap, count), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+use crate::memory::layout::{MAX_DATA_SIZE, MAX_HEAP_SIZE, MAX_STACK_SIZE};
+
+/// Settings of a single VM instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmConfig {
+    /// Size of the stack in bytes, at most `memory::layout::MAX_STACK_SIZE`.
+    pub stack_size: usize,
+    /// Size of the data segment in bytes, at most `memory::layout::MAX_DATA_SIZE`.
+    pub data_size: usize,
+    /// Size the heap starts out with in bytes, at most `memory::layout::MAX_HEAP_SIZE`.
+    pub heap_size: usize,
+    /// Size the heap may grow to when an allocation does not fit. The heap never grows if this is
+    /// not larger than `heap_size`. At most `memory::layout::MAX_HEAP_SIZE`.
+    pub max_heap_size: usize,
+    /// Whether heap accesses are checked against shadow memory, see `memory::memcheck`.
+    pub memcheck: bool,
+    /// Enables the garbage collector, which collects once this many bytes were allocated since the
+    /// last collection, see `memory::gc`.
+    pub gc_threshold: Option<usize>,
+    /// Fails calls nested deeper than this with a stack overflow.
+    pub max_call_depth: Option<usize>,
+    /// Whether values are popped with the width they were pushed with, see `memory::tags`.
+    pub stack_tags: bool,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            stack_size: 64 * 1024,
+            data_size: 64 * 1024,
+            heap_size: 1024 * 1024,
+            max_heap_size: 1024 * 1024,
+            memcheck: false,
+            gc_threshold: None,
+            max_call_depth: None,
+            stack_tags: false,
+        }
+    }
+}
+
+impl VmConfig {
+    /// Lets the heap grow up to `max_heap_size` bytes.
+    pub fn growable_heap(mut self, max_heap_size: usize) -> Self {
+        self.max_heap_size = max_heap_size;
+        self
+    }
+
+    /// Checks that every size fits into its region of the address space.
+    pub fn validate(&self) -> Result<(), String> {
+        let sizes = [
+            ("Stack size", self.stack_size, MAX_STACK_SIZE, "stack"),
+            ("Data size", self.data_size, MAX_DATA_SIZE, "data segment"),
+            ("Heap size", self.heap_size, MAX_HEAP_SIZE, "heap"),
+            ("Maximum heap size", self.max_heap_size, MAX_HEAP_SIZE, "heap"),
+        ];
+        for (name, size, limit, region) in sizes {
+            if size > limit {
+                return Err(format!("{} {} exceeds the {} bytes of the {} region", name, size, limit, region));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The default number of bytes allocated between garbage collections.
+pub const DEFAULT_GC_THRESHOLD: usize = 256 * 1024;
+
+/// Parses a number of bytes with an optional `K`, `M` or `G` suffix, like `64K`.
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let (number, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => size.split_at(index),
+        None => (size, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown size unit {:?}", unit)),
+    };
+    let number: usize = number.parse().map_err(|_| format!("Invalid size {:?}", size))?;
+    number.checked_mul(multiplier).ok_or_else(|| format!("Size {:?} is too large", size))
+}
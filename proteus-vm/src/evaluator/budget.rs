@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use super::{Evaluator, Outcome};
+
+/// Reading the clock is expensive compared to an instruction, so the deadline is only checked
+/// every this many instructions.
+const CLOCK_CHECK_INTERVAL: u32 = 1024;
+
+impl<'a> Evaluator<'a> {
+    /// Limits the number of instructions `evaluate` may execute. `None` removes the limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Grants additional instructions, e.g. to resume after `Outcome::OutOfFuel`. Without a limit,
+    /// this sets one of `fuel` instructions.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(self.fuel.map_or(fuel, |remaining| remaining.saturating_add(fuel)));
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Stops evaluation with `Outcome::TimedOut` once `deadline` has passed.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.clock_countdown = 0;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.set_deadline(Some(Instant::now() + timeout));
+    }
+
+    #[cfg(feature = "jit")]
+    pub(super) fn has_budget(&self) -> bool {
+        self.fuel.is_some() || self.deadline.is_some()
+    }
+
+    /// Checks whether execution has to stop before the next instruction.
+    pub(super) fn budget_exhausted(&mut self) -> Option<Outcome> {
+        if self.halt {
+            return None;
+        }
+        if let Some(deadline) = self.deadline {
+            if self.clock_countdown == 0 {
+                self.clock_countdown = CLOCK_CHECK_INTERVAL;
+                if Instant::now() >= deadline {
+                    return Some(Outcome::TimedOut);
+                }
+            }
+            self.clock_countdown -= 1;
+        }
+        match self.fuel {
+            Some(0) => Some(Outcome::OutOfFuel),
+            _ => None,
+        }
+    }
+
+    pub(super) fn consume_fuel(&mut self) {
+        if let Some(fuel) = &mut self.fuel {
+            *fuel = fuel.saturating_sub(1);
+        }
+    }
+}
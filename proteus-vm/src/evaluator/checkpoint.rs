@@ -0,0 +1,88 @@
+//! Checkpoints of a paused evaluator, so that long-running programs survive a restart.
+//!
+//! A checkpoint holds everything the program can observe: the instruction counter, the call frames
+//! and the whole memory, including the free lists of the allocator and the state of the debugging
+//! modes. It does not hold the state of host functions, so open files and the random number
+//! generator start over after a restore, and neither does it hold the budget, which the host sets
+//! again.
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::layout::STACK_START;
+use crate::memory::Memory;
+use crate::utils::fnv1a;
+
+use super::Evaluator;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The FNV-1a hash of the byte code, which must match to restore the checkpoint.
+    pub byte_code_hash: u64,
+    pub instruction_counter: usize,
+    pub halt: bool,
+    pub exit_code: i32,
+    pub stack_frames: Vec<u32>,
+    pub memory: Memory,
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid checkpoint: {}", e))
+    }
+
+    /// Checks that the instruction counter and the stack pointers lie within the program and the
+    /// stack, and that the memory is consistent, since a checkpoint read from a file may have been
+    /// edited.
+    fn validate(&self, instruction_count: usize) -> Result<(), String> {
+        if self.instruction_counter > instruction_count {
+            return Err(format!(
+                "Invalid checkpoint: instruction {} is past the end of the program with {} instructions",
+                self.instruction_counter, instruction_count
+            ));
+        }
+        self.memory.validate().map_err(|e| format!("Invalid checkpoint: {}", e))?;
+        let stack_pointer = self.memory.stack_pointer;
+        if self.stack_frames.is_empty() {
+            return Err("Invalid checkpoint: no stack frames".to_string());
+        }
+        if let Some(frame) = self.stack_frames.iter().find(|frame| !(STACK_START..=stack_pointer).contains(&(**frame as usize))) {
+            return Err(format!("Invalid checkpoint: stack frame at {} is outside the used stack", frame));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Evaluator<'a> {
+    /// Captures the state of the program, to continue it later with `restore`. Only call this
+    /// while the evaluator is paused, e.g. after `evaluate` returned `Outcome::OutOfFuel`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            byte_code_hash: fnv1a(self.byte_code),
+            instruction_counter: self.byte_code_parser.instruction_counter,
+            halt: self.halt,
+            exit_code: self.exit_code,
+            stack_frames: self.stack_frames.clone(),
+            memory: self.memory.clone(),
+        }
+    }
+
+    /// Continues the program from `checkpoint`, which has to be taken from the same byte code.
+    /// The memory is replaced as a whole, so its sizes and debugging modes are the ones of the
+    /// evaluator the checkpoint was taken from.
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), String> {
+        if checkpoint.byte_code_hash != fnv1a(self.byte_code) {
+            return Err("The checkpoint was taken from a different program".to_string());
+        }
+        checkpoint.validate(self.byte_code_parser.instruction_count())?;
+        self.byte_code_parser.go_to(checkpoint.instruction_counter);
+        self.halt = checkpoint.halt;
+        self.exit_code = checkpoint.exit_code;
+        self.stack_frames = checkpoint.stack_frames;
+        self.memory = checkpoint.memory;
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::memory::StackError;
+
+use super::Evaluator;
+
+/// An error that stops the VM, located at the instruction that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /// The stack ran out of room, or a call went deeper than `VmConfig::max_call_depth`.
+    StackOverflow { instruction: usize, call_depth: usize, message: String },
+    /// An instruction took more from the stack than there was on it.
+    StackUnderflow { instruction: usize, call_depth: usize, message: String },
+    /// An instruction popped a value with a different width than it was pushed with, see
+    /// `VmConfig::stack_tags`.
+    WidthMismatch { instruction: usize, call_depth: usize, message: String },
+    /// `idiv` or `imod` divided by zero.
+    DivisionByZero { instruction: usize, call_depth: usize, message: String },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::StackOverflow { instruction, call_depth, message }
+            | VmError::StackUnderflow { instruction, call_depth, message }
+            | VmError::WidthMismatch { instruction, call_depth, message }
+            | VmError::DivisionByZero { instruction, call_depth, message } => {
+                write!(f, "{} (instruction {}, call depth {})", message, instruction, call_depth)
+            }
+        }
+    }
+}
+
+impl Error for VmError {}
+
+/// A division by zero, before it is located at an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DivisionByZero;
+
+impl fmt::Display for DivisionByZero {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Division by zero")
+    }
+}
+
+impl Error for DivisionByZero {}
+
+impl<'a> Evaluator<'a> {
+    /// The number of calls that have not returned yet.
+    pub fn call_depth(&self) -> usize {
+        self.stack_frames.len().saturating_sub(1)
+    }
+
+    /// Turns stack errors and divisions by zero into `VmError`s that name the current instruction
+    /// and call depth.
+    pub(super) fn locate_error(&self, error: Box<dyn Error>) -> Box<dyn Error> {
+        let instruction = self.byte_code_parser.instruction_counter.saturating_sub(1);
+        let call_depth = self.call_depth();
+        let error = match error.downcast::<StackError>() {
+            Ok(error) => *error,
+            Err(error) if error.is::<DivisionByZero>() => {
+                let message = error.to_string();
+                return Box::new(VmError::DivisionByZero { instruction, call_depth, message });
+            }
+            Err(error) => return error,
+        };
+        let message = error.to_string();
+        Box::new(match error {
+            StackError::Overflow { .. } => VmError::StackOverflow { instruction, call_depth, message },
+            StackError::Underflow { .. } => VmError::StackUnderflow { instruction, call_depth, message },
+            StackError::WidthMismatch { .. } | StackError::SplitValue { .. } => {
+                VmError::WidthMismatch { instruction, call_depth, message }
+            }
+        })
+    }
+
+    /// Fails the call at the current instruction if it would exceed the maximum call depth.
+    pub(super) fn check_call_depth(&self) -> Result<(), VmError> {
+        match self.max_call_depth {
+            Some(limit) if self.call_depth() >= limit => Err(VmError::StackOverflow {
+                instruction: self.byte_code_parser.instruction_counter.saturating_sub(1),
+                call_depth: self.call_depth(),
+                message: format!("Stack overflow: calls are limited to a depth of {}", limit),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Names `instruction` in the message of `error`, unless it already is a located `VmError`.
+pub(super) fn in_instruction(instruction: &impl fmt::Debug, error: Box<dyn Error>) -> Box<dyn Error> {
+    if error.is::<VmError>() {
+        return error;
+    }
+    format!("Error while evaluating instruction {:?}: {}", instruction, error).into()
+}
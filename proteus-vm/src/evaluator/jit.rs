@@ -0,0 +1,89 @@
+use std::error::Error;
+
+use crate::jit::{Jit, JitContext, STATUS_BAIL, STATUS_CONTINUE, STATUS_ERROR, STATUS_HALTED};
+
+use super::{error, Evaluator};
+
+impl<'a> Evaluator<'a> {
+    /// Compiles functions to native code once they have been called `threshold` times.
+    pub fn enable_jit(&mut self, threshold: u32) {
+        self.jit = Some(Jit::new(threshold));
+    }
+
+    pub fn jit(&self) -> Option<&Jit> {
+        self.jit.as_ref()
+    }
+
+    /// Runs the function at `entry` natively if it is hot, after `call` has set up its frame.
+    pub(super) fn enter_jit(&mut self, entry: u32) -> Result<(), Box<dyn Error>> {
+        // Compiled code does not count instructions or tag the stack.
+        if self.has_budget() || self.memory.tags.is_some() {
+            return Ok(());
+        }
+        let function = match &mut self.jit {
+            Some(jit) => jit.lookup(entry, &self.byte_code_parser),
+            None => None,
+        };
+        let Some(function) = function else {
+            return Ok(());
+        };
+        let frame_pointer = *self.stack_frames.last().unwrap();
+        let mut context = JitContext::new(self as *mut Self, frame_pointer);
+        context.sync_memory(&mut self.memory);
+        let status = unsafe { function(&mut context) };
+        self.memory.stack_pointer = context.stack_address();
+        self.memory.record_stack_depth(context.peak_stack_pointer as usize);
+        match status {
+            STATUS_BAIL => self.byte_code_parser.go_to(context.instruction_counter as usize),
+            STATUS_ERROR => {
+                return Err(context.error.take().unwrap_or_else(|| "Compiled code failed".into()));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub(crate) fn jit_execute_instruction(&mut self, context: &mut JitContext<'a>, instruction_counter: u32) -> u32 {
+        self.memory.stack_pointer = context.stack_address();
+        self.byte_code_parser.go_to(instruction_counter as usize);
+        let result = match self.next() {
+            Some(instruction) => {
+                self.evaluate_instruction(&instruction).map_err(|e| error::in_instruction(&instruction, e))
+            }
+            None => Err("Compiled code reached the end of the program".into()),
+        };
+        self.jit_status(context, result)
+    }
+
+    pub(crate) fn jit_call(&mut self, context: &mut JitContext<'a>, instruction_counter: u32, destination: u32) -> u32 {
+        self.memory.stack_pointer = context.stack_address();
+        self.byte_code_parser.go_to(instruction_counter as usize + 1);
+        let depth = self.stack_frames.len();
+        let result = self.call(destination).and_then(|_| self.run_until_frame_depth(depth));
+        self.jit_status(context, result)
+    }
+
+    fn run_until_frame_depth(&mut self, depth: usize) -> Result<(), Box<dyn Error>> {
+        while self.stack_frames.len() > depth {
+            match self.next() {
+                Some(instruction) => {
+                    self.evaluate_instruction(&instruction).map_err(|e| error::in_instruction(&instruction, e))?
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn jit_status(&mut self, context: &mut JitContext<'a>, result: Result<(), Box<dyn Error>>) -> u32 {
+        context.sync_memory(&mut self.memory);
+        match result {
+            Err(e) => {
+                context.error = Some(e);
+                STATUS_ERROR
+            }
+            Ok(()) if self.halt => STATUS_HALTED,
+            Ok(()) => STATUS_CONTINUE,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::instructions::OpCode;
+use crate::memory::describe_site;
+use crate::memory::heap::Allocation;
+
+use super::Evaluator;
+
+impl<'a> Evaluator<'a> {
+    /// Lists every heap allocation that was not freed, with its size and the instruction and
+    /// function that made it.
+    pub fn leak_report(&self) -> String {
+        let heap = &self.memory.heap;
+        if heap.allocation_count() == 0 {
+            return "Leak report: all heap allocations were freed".to_string();
+        }
+        let leaked: usize = heap.allocations().map(|(_, allocation)| allocation.size).sum();
+        let mut report = match heap.allocation_count() {
+            1 => format!("Leak report: 1 allocation with {} bytes was not freed", leaked),
+            count => format!("Leak report: {} allocations with {} bytes were not freed", count, leaked),
+        };
+        self.list_allocations(&mut report, |_| String::new());
+        report
+    }
+
+    /// Lists every object that is still referenced, with its reference count, size and the
+    /// instruction and function that made it.
+    pub fn refcount_report(&self) -> String {
+        let heap = &self.memory.heap;
+        let mut report = match heap.allocation_count() {
+            0 => return "Refcount report: no objects are still referenced".to_string(),
+            1 => "Refcount report: 1 object is still referenced".to_string(),
+            count => format!("Refcount report: {} objects are still referenced", count),
+        };
+        self.list_allocations(&mut report, |start| match heap.reference_count(start).unwrap() {
+            1 => " with 1 reference".to_string(),
+            references => format!(" with {} references", references),
+        });
+        report
+    }
+
+    /// Summarizes heap and stack usage and lists every live allocation with its size and the
+    /// instruction and function that made it.
+    pub fn memory_report(&self) -> String {
+        let mut report = "Memory stats:".to_string();
+        for line in self.memory.stats().to_string().lines() {
+            report.push_str(&format!("\n  {}", line));
+        }
+        self.list_allocations(&mut report, |_| String::new());
+        report
+    }
+
+    /// Appends a line for every live allocation with its size, its address, what `details` says
+    /// about the allocation at that heap offset and the instruction and function that made it.
+    fn list_allocations(&self, report: &mut String, details: impl Fn(usize) -> String) {
+        let functions = self.functions();
+        for (start, allocation) in self.memory.heap.allocations() {
+            report.push_str(&format!(
+                "\n  {} bytes at {}{}, {}",
+                allocation.size,
+                start + self.memory.heap_start(),
+                details(start),
+                describe_origin(allocation, &functions)
+            ));
+        }
+    }
+
+    /// The first instruction of every function, in order.
+    pub(super) fn functions(&self) -> Vec<usize> {
+        let mut functions: Vec<usize> = (0..self.byte_code_parser.instruction_count())
+            .filter_map(|index| self.byte_code_parser.instruction_at(index).ok())
+            .filter(|instruction| instruction.opcode == OpCode::CALL)
+            .map(|instruction| instruction.operand as usize)
+            .collect();
+        functions.sort_unstable();
+        functions.dedup();
+        functions
+    }
+}
+
+/// Which instruction in which function made the allocation.
+pub(super) fn describe_origin(allocation: &Allocation, functions: &[usize]) -> String {
+    let mut origin = format!("allocated by {}", describe_site(allocation.site));
+    if let Some(site) = allocation.site {
+        match functions.iter().rev().find(|function| **function <= site) {
+            Some(function) => origin.push_str(&format!(" in the function at instruction {}", function)),
+            None => origin.push_str(" at the top level"),
+        }
+    }
+    origin
+}
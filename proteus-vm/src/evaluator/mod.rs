@@ -1,27 +1,64 @@
 use std::error::Error;
+use std::time::Instant;
 
-use crate::ffi::{FFIFunction, FFIType, FFIValue};
+use crate::config::VmConfig;
+use crate::ffi::{FFIType, FFIValue, HostContext, HostFunctions};
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
 use crate::instructions::instruction::Instruction;
 use crate::instructions::OpCode;
+use crate::ir::BinaryOp;
 use crate::loading::ByteCodeParser;
-use crate::memory::heap::Heap;
-use crate::memory::Memory;
-use crate::utils::{decode_signed, decode_unsigned, encode_signed, encode_unsigned};
-
-pub fn evaluate(byte_code: &[u8]) -> Result<(), Box<dyn Error>> {
-    let mut evaluator = Evaluator::new(byte_code);
+use crate::memory::layout::STACK_START;
+use crate::memory::{Memory, StackError};
+use crate::utils::{decode_signed, encode_signed, encode_unsigned};
+
+mod budget;
+mod checkpoint;
+mod error;
+mod leaks;
+#[cfg(feature = "jit")]
+mod jit;
+mod registers;
+
+pub use checkpoint::Checkpoint;
+pub use error::{DivisionByZero, VmError};
+
+pub fn evaluate(byte_code: &[u8]) -> Result<Outcome, Box<dyn Error>> {
+    let mut evaluator = Evaluator::new(byte_code)?;
     evaluator.evaluate()
 }
 
+/// Why the evaluator stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Halted,
+    /// The instruction budget is used up. Add fuel and evaluate again to continue.
+    OutOfFuel,
+    /// The deadline has passed. Set a new one and evaluate again to continue.
+    TimedOut,
+}
+
 pub struct Evaluator<'a> {
     halt: bool,
+    byte_code: &'a [u8],
     pub byte_code_parser: ByteCodeParser<'a>,
     stack_frames: Vec<u32>,
     pub memory: Memory,
+    host_functions: HostFunctions,
+    /// The host function index of every import of the program.
+    imports: Vec<usize>,
+    exit_code: i32,
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    clock_countdown: u32,
+    max_call_depth: Option<usize>,
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
 }
 
 impl<'a> Evaluator<'a> {
-    pub(crate) fn print_state(&self) {
+    pub fn print_state(&self) {
         println!(
             "Current stack frame offset: {}",
             self.stack_frames.last().unwrap_or(&0)
@@ -59,27 +96,71 @@ const POINTER_SIZE: usize = 4;
 
 
 impl<'a> Evaluator<'a> {
-    pub fn new(instructions: &'a [u8]) -> Self {
-        Self {
+    pub fn new(instructions: &'a [u8]) -> Result<Self, String> {
+        Self::with_host_functions(instructions, HostFunctions::standard())
+    }
+
+    /// Loads the program and links its imports against `host_functions`.
+    pub fn with_host_functions(instructions: &'a [u8], host_functions: HostFunctions) -> Result<Self, String> {
+        Self::with_config(instructions, host_functions, &VmConfig::default())
+    }
+
+    pub fn with_config(instructions: &'a [u8], mut host_functions: HostFunctions, config: &VmConfig) -> Result<Self, String> {
+        let byte_code_parser = ByteCodeParser::new(instructions)?;
+        let imports = match byte_code_parser.imports() {
+            Some(imports) => {
+                let mut indices = Vec::new();
+                let mut unknown = Vec::new();
+                for import in imports {
+                    match host_functions.link(import)? {
+                        Some(index) => indices.push(index),
+                        None => unknown.push(import.as_str()),
+                    }
+                }
+                if !unknown.is_empty() {
+                    return Err(format!("Unknown FFI functions: {}", unknown.join(", ")));
+                }
+                indices
+            }
+            None => (0..host_functions.len()).collect(),
+        };
+        Ok(Self {
             halt: false,
-            byte_code_parser: ByteCodeParser::new(instructions),
-            stack_frames: vec![0],
-            memory: Memory::new(),
-        }
+            byte_code: instructions,
+            byte_code_parser,
+            stack_frames: vec![STACK_START as u32],
+            memory: Memory::with_config(config)?,
+            host_functions,
+            imports,
+            exit_code: 0,
+            fuel: None,
+            deadline: None,
+            clock_countdown: 0,
+            max_call_depth: config.max_call_depth,
+            #[cfg(feature = "jit")]
+            jit: None,
+        })
     }
 
-    pub fn evaluate(&mut self) -> Result<(), Box<dyn Error>> {
-        while let Some(instruction) = self.next() {
-            self.evaluate_instruction(&instruction).map_err(
-                |e| {
-                    format!(
-                        "Error while evaluating instruction {:?}: {}",
-                        instruction, e
-                    )
-                }
-            ).unwrap();
+    /// Runs until the program halts or its budget is exhausted.
+    pub fn evaluate(&mut self) -> Result<Outcome, Box<dyn Error>> {
+        loop {
+            if let Some(outcome) = self.budget_exhausted() {
+                return Ok(outcome);
+            }
+            let Some(instruction) = self.next() else {
+                break;
+            };
+            self.consume_fuel();
+            self.evaluate_instruction(&instruction)
+                .map_err(|e| error::in_instruction(&instruction, e))?;
         }
-        Ok(())
+        Ok(Outcome::Halted)
+    }
+
+    /// The status the program asked to exit with, 0 unless it called `exit`.
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
     }
 
     pub fn evaluate_instruction(
@@ -88,9 +169,12 @@ impl<'a> Evaluator<'a> {
     ) -> Result<(), Box<dyn Error>> {
         let operand = instruction.operand;
         let offset = instruction.offset;
-        match instruction.opcode {
+        if let Some(tags) = &mut self.memory.tags {
+            tags.set_site(self.byte_code_parser.instruction_counter.checked_sub(1));
+        }
+        let result = match instruction.opcode {
             OpCode::ALLOC => self.alloc(operand as u32),
-            OpCode::FREE => self.free(operand as u32),
+            OpCode::FREE => self.at_site(|evaluator| evaluator.free(operand as u32)),
             OpCode::LOAD => self.load(operand, offset),
             OpCode::STORE => self.store(operand, offset),
             OpCode::PUSH => self.push(operand),
@@ -101,8 +185,8 @@ impl<'a> Evaluator<'a> {
             OpCode::IMOD => self.imod(),
             OpCode::IEQ => self.ieq(),
             OpCode::ILT => self.ilt(),
-            OpCode::ILE => self.ilt(),
-            OpCode::IAND => self.iadd(),
+            OpCode::ILE => self.ile(),
+            OpCode::IAND => self.iand(),
             OpCode::IOR => self.ior(),
             OpCode::IXOR => self.ixor(),
             OpCode::INOT => self.inot(),
@@ -119,8 +203,8 @@ impl<'a> Evaluator<'a> {
             OpCode::IGT => self.igt(),
             OpCode::IGE => self.ige(),
             OpCode::INE => self.ine(),
-            OpCode::HALLOC => self.halloc(operand as u32),
-            OpCode::FFCALL => self.ffcall(operand as u32),
+            OpCode::HALLOC => self.at_site(|evaluator| evaluator.halloc(operand)),
+            OpCode::FFCALL => self.at_site(|evaluator| evaluator.ffcall(operand as u32)),
             OpCode::SADD => self.sadd(),
             OpCode::PUSHB => self.pushb(operand as u8),
             OpCode::ITOA => self.itoa(),
@@ -130,9 +214,28 @@ impl<'a> Evaluator<'a> {
             OpCode::PUSHSP => self.pushsp(operand),
             OpCode::RLOAD => self.rload(operand, offset),
             OpCode::RSTORE => self.rstore(operand, offset),
-            OpCode::DHALLOC => self.dhalloc(),
+            OpCode::MEMCPY => self.memcpy(operand as u32),
+            OpCode::MEMSET => self.memset(operand as u32),
+            OpCode::MEMCMP => self.memcmp(operand as u32),
+            OpCode::DHALLOC => self.at_site(|evaluator| evaluator.dhalloc()),
             OpCode::BTOA => self.btoa(),
-        }
+            OpCode::HREALLOC => self.at_site(|evaluator| evaluator.hrealloc(operand as u32)),
+            OpCode::RETAIN => self.retain(),
+            OpCode::RELEASE => self.at_site(|evaluator| evaluator.release()),
+            OpCode::GC => self.at_site(|evaluator| {
+                evaluator.memory.collect_garbage();
+                Ok(())
+            }),
+        };
+        result.map_err(|e| self.locate_error(e))
+    }
+
+    /// Attributes the heap allocations and frees of `operation` to the current instruction.
+    fn at_site(&mut self, operation: impl FnOnce(&mut Self) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        self.memory.heap.set_site(self.byte_code_parser.instruction_counter.checked_sub(1));
+        let result = operation(self);
+        self.memory.heap.set_site(None);
+        result
     }
 
     fn pushsp(&mut self, offset: i32) -> Result<(), Box<dyn Error>> {
@@ -143,17 +246,22 @@ impl<'a> Evaluator<'a> {
 
     /// Add the given number of elements to the stack
     fn alloc(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
-        if self.stack_frames.len() < 1 {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Alloc called without a stack frame. This most likely means that you are trying to allocate memory outside of a function. Use halloc instead.")));
+        if self.stack_frames.is_empty() {
+            return Err(Box::new(std::io::Error::other("Alloc called without a stack frame. This most likely means that you are trying to allocate memory outside of a function. Use halloc instead.")));
         }
-        self.memory.move_stack_pointer_by(bytes as usize);
+        self.memory.move_stack_pointer_by(bytes as usize)?;
         Ok(())
     }
 
 
-    fn free(&mut self, index: u32) -> Result<(), Box<dyn Error>> {
+    /// Frees `bytes` bytes at the popped address, or the whole heap allocation if `bytes` is 0.
+    fn free(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
         let ptr = self.remove_top()?;
-        self.memory.free(ptr as usize, index as usize)?;
+        if bytes == 0 {
+            self.memory.free_allocation(ptr as usize)?;
+        } else {
+            self.memory.free(ptr as usize, bytes as usize)?;
+        }
         Ok(())
     }
 
@@ -170,7 +278,7 @@ impl<'a> Evaluator<'a> {
     fn iadd(&mut self) -> Result<(), Box<dyn Error>> {
         let a = self.remove_top()?;
         let b = self.remove_top()?;
-        let result = a + b;
+        let result = a.wrapping_add(b);
         self.push(result)?;
         Ok(())
     }
@@ -178,16 +286,16 @@ impl<'a> Evaluator<'a> {
     fn sadd(&mut self) -> Result<(), Box<dyn Error>> {
         let mut s: Vec<u8> = vec![];
 
-        while let Ok(byte) = self.memory.pop(1) {
-            let byte = byte[0];
+        while self.memory.stack_depth() > 0 {
+            let byte = self.memory.pop_value(1)?[0];
             if byte == 0 {
                 break;
             }
             s.push(byte);
         }
 
-        while let Ok(byte) = self.memory.pop(1) {
-            let byte = byte[0];
+        while self.memory.stack_depth() > 0 {
+            let byte = self.memory.pop_value(1)?[0];
             s.push(byte);
             if byte == 0 {
                 break;
@@ -214,47 +322,47 @@ impl<'a> Evaluator<'a> {
     fn isub(&mut self) -> Result<(), Box<dyn Error>> {
         let a = self.remove_top()?;
         let b = self.remove_top()?;
-        let result = a - b;
+        let result = a.wrapping_sub(b);
         self.memory.push(&encode_signed(result))?;
         Ok(())
     }
 
-    pub fn remove_top(&mut self) -> Result<i32, String> {
-        decode_signed(0, &self.memory.pop(POINTER_SIZE)?)
+    pub fn remove_top(&mut self) -> Result<i32, Box<dyn Error>> {
+        Ok(decode_signed(0, self.memory.pop_value(POINTER_SIZE)?)?)
     }
 
-    pub fn remove_top_byte(&mut self) -> Result<u8, String> {
-        let result = self.memory.pop(1)?[0];
+    pub fn remove_top_byte(&mut self) -> Result<u8, Box<dyn Error>> {
+        let result = self.memory.pop_value(1)?[0];
         Ok(result)
     }
 
-    pub fn remove_top_bytes(&mut self, bytes: u32) -> Result<Vec<u8>, String> {
+    pub fn remove_top_bytes(&mut self, bytes: u32) -> Result<Vec<u8>, Box<dyn Error>> {
         let result = self.memory.pop(bytes as usize)?;
         Ok(result.to_vec())
     }
 
-    fn read_top(&self) -> Result<i32, String> {
-        let result = decode_signed(0, &self.memory.peek(POINTER_SIZE)?)?;
+    fn read_top(&self) -> Result<i32, Box<dyn Error>> {
+        let result = decode_signed(0, self.memory.peek_value(POINTER_SIZE)?)?;
         Ok(result)
     }
     fn imul(&mut self) -> Result<(), Box<dyn Error>> {
         let a = self.remove_top()?;
         let b = self.remove_top()?;
-        let result = a * b;
+        let result = a.wrapping_mul(b);
         self.push(result)?;
         Ok(())
     }
     fn idiv(&mut self) -> Result<(), Box<dyn Error>> {
         let a = self.remove_top()?;
         let b = self.remove_top()?;
-        let result = a / b;
+        let result = BinaryOp::Div.apply(a, b)?;
         self.push(result)?;
         Ok(())
     }
     fn imod(&mut self) -> Result<(), Box<dyn Error>> {
         let a = self.remove_top()?;
         let b = self.remove_top()?;
-        let result = a % b;
+        let result = BinaryOp::Mod.apply(a, b)?;
         self.push(result)?;
 
         Ok(())
@@ -284,6 +392,15 @@ impl<'a> Evaluator<'a> {
         Ok(())
     }
 
+    fn ile(&mut self) -> Result<(), Box<dyn Error>> {
+        let a = self.remove_top()?;
+        let b = self.remove_top()?;
+        let result = (a <= b) as i32;
+        self.push(result)?;
+
+        Ok(())
+    }
+
     fn igt(&mut self) -> Result<(), Box<dyn Error>> {
         let a = self.remove_top()?;
         let b = self.remove_top()?;
@@ -302,13 +419,21 @@ impl<'a> Evaluator<'a> {
         Ok(())
     }
 
+    fn iand(&mut self) -> Result<(), Box<dyn Error>> {
+        let a = self.remove_top()?;
+        let b = self.remove_top()?;
+        let result = a & b;
+        self.push(result)?;
+
+        Ok(())
+    }
+
     fn ior(&mut self) -> Result<(), Box<dyn Error>> {
         let a = self.remove_top()?;
         let b = self.remove_top()?;
         let result = a | b;
         self.push(result)?;
 
-
         Ok(())
     }
     fn ixor(&mut self) -> Result<(), Box<dyn Error>> {
@@ -345,10 +470,13 @@ impl<'a> Evaluator<'a> {
         Ok(())
     }
     fn call(&mut self, dest: u32) -> Result<(), Box<dyn Error>> {
+        self.check_call_depth()?;
         let address = self.byte_code_parser.instruction_counter as u32;
         self.push(address as i32)?;
         self.stack_frames.push(self.memory.stack_pointer as u32);
         self.jmp(dest)?;
+        #[cfg(feature = "jit")]
+        self.enter_jit(dest)?;
         Ok(())
     }
     fn pop(&mut self) -> Result<(), Box<dyn Error>> {
@@ -358,29 +486,59 @@ impl<'a> Evaluator<'a> {
     fn iret(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
         // self.swap()?;
         let value = self.remove_top_bytes(bytes)?;
-        self.clear_stack_frame();
+        self.clear_stack_frame()?;
         let dest = self.remove_top()?;
         self.memory.push(&value)?;
         self.jmp(dest as u32)
     }
 
-    fn clear_stack_frame(&mut self) {
+    fn clear_stack_frame(&mut self) -> Result<(), StackError> {
         let offset = self.stack_frames.pop();
         if let Some(offset) = offset {
-            self.memory.move_stack_pointer_to(offset as usize)
+            self.memory.move_stack_pointer_to(offset as usize)?;
         }
+        Ok(())
     }
 
-    fn halloc(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
-        let pointer = self.memory.allocate_heap(bytes as usize)?;
+    fn halloc(&mut self, bytes: i32) -> Result<(), Box<dyn Error>> {
+        let bytes = usize::try_from(bytes).map_err(|_| format!("Negative size {}", bytes))?;
+        let pointer = self.memory.allocate_heap(bytes)?;
         self.memory.push(
             &encode_unsigned(pointer as u32))?;
         Ok(())
     }
 
+    /// Pops an address and resizes its allocation to `bytes` bytes, or to the popped size if `bytes`
+    /// is 0, and pushes the address it ends up at. A null address allocates.
+    fn hrealloc(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
+        let pointer = self.remove_address()?;
+        let bytes = self.block_size(bytes)?;
+        let pointer = match pointer {
+            0 => self.memory.allocate_heap(bytes)?,
+            pointer => self.memory.reallocate_heap(pointer, bytes)?,
+        };
+        self.push(pointer as i32)?;
+        Ok(())
+    }
+
+    /// Adds a reference to the allocation whose address is on top of the stack, leaving the address.
+    fn retain(&mut self) -> Result<(), Box<dyn Error>> {
+        let pointer = decode_signed(0, self.memory.peek_value(POINTER_SIZE)?)?;
+        self.memory.retain(pointer as usize)?;
+        Ok(())
+    }
+
+    /// Drops a reference to the allocation at the popped address, freeing it when none remain.
+    fn release(&mut self) -> Result<(), Box<dyn Error>> {
+        let pointer = self.remove_top()?;
+        self.memory.release(pointer as usize)?;
+        Ok(())
+    }
+
     fn dhalloc(&mut self) -> Result<(), Box<dyn Error>> {
         let bytes = self.remove_top()?;
-        let pointer = self.memory.allocate_heap(bytes as usize)?;
+        let bytes = usize::try_from(bytes).map_err(|_| format!("Negative size {}", bytes))?;
+        let pointer = self.memory.allocate_heap(bytes)?;
         self.push(pointer as i32)?;
         Ok(())
     }
@@ -407,7 +565,7 @@ impl<'a> Evaluator<'a> {
         let value = &self.remove_top_bytes(bytes)?;
         let base_address = self.stack_frames.last().unwrap();
         let address = (*base_address) as i32 + offset;
-        self.memory.store(address as usize, &value)?;
+        self.memory.store(address as usize, value)?;
         Ok(())
     }
 
@@ -415,7 +573,7 @@ impl<'a> Evaluator<'a> {
         let value = &self.remove_top_bytes(bytes)?;
         let base_address = self.read_top()?;
         let address = base_address + offset;
-        self.memory.store(address as usize, &value)?;
+        self.memory.store(address as usize, value)?;
         Ok(())
     }
 
@@ -427,45 +585,144 @@ impl<'a> Evaluator<'a> {
         Ok(())
     }
 
-    fn ffcall(&mut self, function_name: u32) -> Result<(), Box<dyn Error>> {
-        let function = FFIFunction::find(&(function_name as usize))
-            .ok_or(format!("Function {} not found", function_name))?;
+    /// Copies `bytes` bytes from the address below the top of the stack to the address on top,
+    /// popping both. The ranges may overlap.
+    fn memcpy(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
+        let destination = self.remove_address()?;
+        let source = self.remove_address()?;
+        let bytes = self.block_size(bytes)?;
+        let data = self.memory.load(source, bytes)?.to_vec();
+        self.memory.store(destination, &data)?;
+        Ok(())
+    }
+
+    /// Pops an address and a value and sets `bytes` bytes at the address to the lowest byte of the
+    /// value.
+    fn memset(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
+        let destination = self.remove_address()?;
+        let value = self.remove_top()? as u8;
+        let bytes = self.block_size(bytes)?;
+        self.memory.check_store(destination, bytes)?;
+        self.memory.store(destination, &vec![value; bytes])?;
+        Ok(())
+    }
+
+    /// Pops two addresses and compares `bytes` bytes at them, pushing -1, 0 or 1 if the bytes at the
+    /// lower address on the stack are less than, equal to or greater than the bytes at the top.
+    fn memcmp(&mut self, bytes: u32) -> Result<(), Box<dyn Error>> {
+        let right = self.remove_address()?;
+        let left = self.remove_address()?;
+        let bytes = self.block_size(bytes)?;
+        let ordering = self.memory.load(left, bytes)?.cmp(self.memory.load(right, bytes)?);
+        self.push(ordering as i32)?;
+        Ok(())
+    }
+
+    fn remove_address(&mut self) -> Result<usize, Box<dyn Error>> {
+        let address = self.remove_top()?;
+        Ok(usize::try_from(address).map_err(|_| format!("Invalid address {}", address))?)
+    }
+
+    /// The number of bytes a block instruction works on: its operand, or the popped value if the
+    /// operand is 0.
+    fn block_size(&mut self, bytes: u32) -> Result<usize, Box<dyn Error>> {
+        let bytes = if bytes == 0 { self.remove_top()? } else { bytes as i32 };
+        Ok(usize::try_from(bytes).map_err(|_| format!("Negative size {}", bytes))?)
+    }
+
+    fn ffcall(&mut self, import: u32) -> Result<(), Box<dyn Error>> {
+        let index = *self.imports.get(import as usize).ok_or(format!("Function {} not found", import))?;
+        let function = self.host_functions.get_mut(index).unwrap();
+        let (arguments, return_type) = (function.arguments.clone(), function.return_type);
         let mut args = Vec::new();
-        for fArg in &function.arguments {
-            let arg = match fArg {
+        for f_arg in &arguments {
+            let arg = match f_arg {
                 FFIType::I32 => FFIValue::I32(self.remove_top()?),
+                // The high word is pushed first, like `store_ffi_result` does.
                 FFIType::I64 => {
-                    let arg = &self.remove_top()?;
-                    let arg2 = &self.remove_top()?;
-                    FFIValue::I64(((*arg as i64) << 32) | (*arg2 as i64))
+                    let low = self.remove_top()?;
+                    let high = self.remove_top()?;
+                    FFIValue::I64(((high as i64) << 32) | (low as u32 as i64))
                 }
                 FFIType::String => {
-                    let address = self.remove_top()?;
-                    let string = self.memory.get_string(address as usize)?;
-                    self.memory.pop(string.len() + 1)?;
+                    let address = self.remove_top()? as usize;
+                    let string = self.memory.get_string(address)?;
+                    if address + string.len() + 1 == self.memory.stack_pointer {
+                        self.memory.pop(string.len() + 1)?;
+                    }
                     FFIValue::String(string)
                 }
+                FFIType::Bytes | FFIType::Buffer => {
+                    let address = self.remove_top()? as usize;
+                    let length = self.remove_top()?;
+                    let length = usize::try_from(length).map_err(|_| format!("Negative size {}", length))?;
+                    let data = self.memory.load(address, length)?.to_vec();
+                    match f_arg {
+                        FFIType::Buffer => FFIValue::Buffer { address, data },
+                        _ => FFIValue::Bytes(data),
+                    }
+                }
                 FFIType::Void => FFIValue::Void,
             };
             args.push(arg);
         }
-        let result = function.call(args)?;
-        self.store_ffi_result(result)
+        let function = self.host_functions.get_mut(index).unwrap();
+        let (result, exit_code) = self.memory.without_gc(|memory| {
+            let mut context = HostContext { memory, exit_code: None };
+            let result = function.call(&mut context, args);
+            (result, context.exit_code)
+        });
+        let result = result?;
+        if let Some(exit_code) = exit_code {
+            self.exit_code = exit_code;
+            self.halt = true;
+        }
+        let matches = matches!(
+            (&result, return_type),
+            (FFIValue::I32(_), FFIType::I32)
+                | (FFIValue::I64(_), FFIType::I64)
+                | (FFIValue::String(_), FFIType::String)
+                | (FFIValue::Bytes(_), FFIType::Bytes)
+                | (FFIValue::Void, FFIType::Void)
+                | (FFIValue::Null, FFIType::String | FFIType::Bytes)
+        );
+        if !matches {
+            return Err(format!("Host function {} returned {:?} instead of {:?}", function.name, result, return_type).into());
+        }
+        self.store_ffi_result(result, return_type)
     }
 
-    fn store_ffi_result(&mut self, value: FFIValue) -> Result<(), Box<dyn Error>> {
-        Ok(match value {
+    fn store_ffi_result(&mut self, value: FFIValue, return_type: FFIType) -> Result<(), Box<dyn Error>> {
+        match value {
             FFIValue::I32(value) => self.push(value)?,
             FFIValue::I64(value) => {
                 self.push((value >> 32) as i32)?;
                 self.push((value & 0xFFFFFFFF) as i32)?;
             }
 
-            FFIValue::String(value) => {
-                unimplemented!("String return values are not implemented yet")
+            FFIValue::String(string) => {
+                let mut data = string.into_bytes();
+                data.push(0);
+                let address = self.memory.allocate_heap_data(&data)?;
+                self.memory.heap.set_pointer_free(address - self.memory.heap_start());
+                self.push(address as i32)?;
             }
-            FFIValue::Void => {}
-        })
+            FFIValue::Bytes(data) => {
+                let address = self.memory.allocate_heap_data(&data)?;
+                self.memory.heap.set_pointer_free(address - self.memory.heap_start());
+                self.push(data.len() as i32)?;
+                self.push(address as i32)?;
+            }
+            // Buffers are only passed to functions, `ffcall` rejects them as results.
+            FFIValue::Void | FFIValue::Buffer { .. } => {}
+            FFIValue::Null => {
+                if return_type == FFIType::Bytes {
+                    self.push(0)?;
+                }
+                self.push(0)?;
+            }
+        }
+        Ok(())
     }
 
     // Converts an integer to a string
@@ -0,0 +1,131 @@
+use std::error::Error;
+
+use crate::ir::{IrInstruction, IrProgram, Operand, Place};
+use crate::utils::{decode_signed, encode_signed};
+
+use super::{error, Evaluator, Outcome};
+
+impl<'a> Evaluator<'a> {
+    /// Runs the program on its register IR instead of the stack byte code.
+    ///
+    /// Fuel is charged per IR instruction. An exhausted budget only suspends execution at the next
+    /// resume point, where the byte code instruction counter is updated so that either interpreter
+    /// can continue.
+    pub fn evaluate_registers(&mut self, program: &IrProgram) -> Result<Outcome, Box<dyn Error>> {
+        let mut registers = vec![0; program.register_count];
+        let mut pc = program.index_of(self.byte_code_parser.instruction_counter)?;
+        while !self.halt {
+            if let Some(&instruction_counter) = program.resume_points.get(&pc) {
+                if let Some(outcome) = self.budget_exhausted() {
+                    self.byte_code_parser.go_to(instruction_counter);
+                    return Ok(outcome);
+                }
+            }
+            self.consume_fuel();
+            let instruction = program.instructions.get(pc).ok_or(
+                "Reached end of programm while parsing. This means that there is either no halt or no return in a function."
+            )?;
+            // Faults name the byte code instruction the stack interpreter would have failed at.
+            self.byte_code_parser.go_to(program.sites[pc] + 1);
+            pc = self
+                .evaluate_ir_instruction(program, pc, instruction, &mut registers)
+                .map_err(|e| self.locate_ir_error(instruction, e))?;
+        }
+        Ok(Outcome::Halted)
+    }
+
+    /// Executes one IR instruction and returns the index of the next one.
+    fn evaluate_ir_instruction(
+        &mut self,
+        program: &IrProgram,
+        pc: usize,
+        instruction: &IrInstruction,
+        registers: &mut [i32],
+    ) -> Result<usize, Box<dyn Error>> {
+        match instruction {
+            IrInstruction::Move { destination, source } => {
+                let value = self.read_operand(*source, registers)?;
+                self.write_place(*destination, value, registers)?;
+            }
+            IrInstruction::Binary { op, destination, a, b } => {
+                let a = self.read_operand(*a, registers)?;
+                let b = self.read_operand(*b, registers)?;
+                self.write_place(*destination, op.apply(a, b)?, registers)?;
+            }
+            IrInstruction::Not { destination, source } => {
+                let value = self.read_operand(*source, registers)?;
+                self.write_place(*destination, !value, registers)?;
+            }
+            IrInstruction::Push(source) => {
+                let value = self.read_operand(*source, registers)?;
+                self.push(value)?;
+            }
+            IrInstruction::Pop(destination) => {
+                let value = self.remove_top()?;
+                self.write_place(*destination, value, registers)?;
+            }
+            IrInstruction::Jump(target) => return Ok(program.index_of(*target)?),
+            IrInstruction::JumpIfZero(condition, target) => {
+                if self.read_operand(*condition, registers)? == 0 {
+                    return Ok(program.index_of(*target)?);
+                }
+            }
+            IrInstruction::JumpIfNotZero(condition, target) => {
+                if self.read_operand(*condition, registers)? != 0 {
+                    return Ok(program.index_of(*target)?);
+                }
+            }
+            IrInstruction::Stack { instruction, instruction_counter } => {
+                let next = instruction_counter + 1;
+                self.byte_code_parser.go_to(next);
+                self.evaluate_instruction(instruction)?;
+                if self.byte_code_parser.instruction_counter != next {
+                    return Ok(program.index_of(self.byte_code_parser.instruction_counter)?);
+                }
+            }
+        }
+        Ok(pc + 1)
+    }
+
+    fn local_address(&self, offset: i32) -> usize {
+        let base_address = self.stack_frames.last().unwrap();
+        ((*base_address as i32) + offset) as u32 as usize
+    }
+
+    fn read_operand(&mut self, operand: Operand, registers: &[i32]) -> Result<i32, String> {
+        match operand {
+            Operand::Immediate(value) => Ok(value),
+            Operand::Register(register) => Ok(registers[register as usize]),
+            Operand::Local(offset, site) => {
+                let value = self.memory.load(self.local_address(offset), 4).and_then(|bytes| decode_signed(0, bytes));
+                value.inspect_err(|_| self.byte_code_parser.go_to(site + 1))
+            }
+        }
+    }
+
+    fn write_place(&mut self, place: Place, value: i32, registers: &mut [i32]) -> Result<(), String> {
+        match place {
+            Place::Register(register) => {
+                registers[register as usize] = value;
+                Ok(())
+            }
+            Place::Local(offset, site) => {
+                let result = self.memory.store(self.local_address(offset), &encode_signed(value));
+                result.inspect_err(|_| self.byte_code_parser.go_to(site + 1))
+            }
+        }
+    }
+
+    /// Names the byte code instruction the fault came from in `error`, like the stack interpreter.
+    fn locate_ir_error(&self, instruction: &IrInstruction, error: Box<dyn Error>) -> Box<dyn Error> {
+        let error = self.locate_error(error);
+        if let IrInstruction::Stack { instruction, .. } = instruction {
+            return error::in_instruction(instruction, error);
+        }
+        let site = self.byte_code_parser.instruction_counter.saturating_sub(1);
+        match self.byte_code_parser.instruction_at(site) {
+            Ok(instruction) => error::in_instruction(&instruction, error),
+            Err(_) => error::in_instruction(instruction, error),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Growable arrays of 1, 2 or 4 byte integers, for lists and string builders.
+
+use super::{i32_argument, string_argument, FFIType, FFIValue, HostFunctions};
+
+pub fn register(functions: &mut HostFunctions) {
+    functions.register("array_new", vec![FFIType::I32, FFIType::I32], FFIType::I32, |context, arguments| {
+        let element_size = index_argument(&arguments, 0)?;
+        let capacity = index_argument(&arguments, 1)?;
+        Ok(FFIValue::I32(context.memory.array_new(element_size, capacity)? as i32))
+    });
+    functions.register("array_len", vec![FFIType::I32], FFIType::I32, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        Ok(FFIValue::I32(context.memory.array_len(array)? as i32))
+    });
+    functions.register("array_capacity", vec![FFIType::I32], FFIType::I32, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        Ok(FFIValue::I32(context.memory.array_capacity(array)? as i32))
+    });
+    // The address of the first element, which changes when the array grows.
+    functions.register("array_data", vec![FFIType::I32], FFIType::I32, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        Ok(FFIValue::I32(context.memory.array_data(array)? as i32))
+    });
+    functions.register("array_get", vec![FFIType::I32, FFIType::I32], FFIType::I32, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        let index = index_argument(&arguments, 1)?;
+        let element = context.memory.array_get(array, index).map_err(|e| format!("array_get: {}", e))?;
+        Ok(FFIValue::I32(decode_element(element)))
+    });
+    functions.register("array_set", vec![FFIType::I32, FFIType::I32, FFIType::I32], FFIType::Void, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        let index = index_argument(&arguments, 1)?;
+        let element = encode_element(i32_argument(&arguments, 2)?, context.memory.array_element_size(array)?);
+        context.memory.array_set(array, index, &element).map_err(|e| format!("array_set: {}", e))?;
+        Ok(FFIValue::Void)
+    });
+    functions.register("array_push", vec![FFIType::I32, FFIType::I32], FFIType::Void, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        let element = encode_element(i32_argument(&arguments, 1)?, context.memory.array_element_size(array)?);
+        context.memory.array_push(array, &element)?;
+        Ok(FFIValue::Void)
+    });
+    functions.register("array_pop", vec![FFIType::I32], FFIType::I32, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        let element = context.memory.array_pop(array).map_err(|e| format!("array_pop: {}", e))?;
+        Ok(FFIValue::I32(decode_element(&element)))
+    });
+    // Appends the bytes of a string to an array of bytes.
+    functions.register("array_append", vec![FFIType::I32, FFIType::String], FFIType::Void, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        let string = string_argument(&arguments, 1)?;
+        byte_array(context.memory.array_element_size(array)?, "array_append")?;
+        context.memory.array_push(array, string.as_bytes())?;
+        Ok(FFIValue::Void)
+    });
+    functions.register("array_to_string", vec![FFIType::I32], FFIType::String, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        byte_array(context.memory.array_element_size(array)?, "array_to_string")?;
+        let length = context.memory.array_len(array)?;
+        let bytes = context.memory.load(context.memory.array_data(array)?, length)?;
+        Ok(FFIValue::String(String::from_utf8_lossy(bytes).into_owned()))
+    });
+    functions.register("array_free", vec![FFIType::I32], FFIType::Void, |context, arguments| {
+        let array = index_argument(&arguments, 0)?;
+        context.memory.array_free(array)?;
+        Ok(FFIValue::Void)
+    });
+}
+
+fn index_argument(arguments: &[FFIValue], index: usize) -> Result<usize, String> {
+    let value = i32_argument(arguments, index)?;
+    usize::try_from(value).map_err(|_| format!("Negative argument {}", value))
+}
+
+fn byte_array(element_size: usize, function: &str) -> Result<(), String> {
+    match element_size {
+        1 => Ok(()),
+        _ => Err(format!("{}: Not an array of bytes", function)),
+    }
+}
+
+/// The lowest `size` bytes of `value`.
+fn encode_element(value: i32, size: usize) -> Vec<u8> {
+    value.to_be_bytes()[4 - size..].to_vec()
+}
+
+/// Bytes are unsigned, larger elements signed.
+fn decode_element(element: &[u8]) -> i32 {
+    match *element {
+        [byte] => byte as i32,
+        [high, low] => i16::from_be_bytes([high, low]) as i32,
+        _ => i32::from_be_bytes(element.try_into().unwrap()),
+    }
+}
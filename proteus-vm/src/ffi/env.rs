@@ -0,0 +1,30 @@
+//! Program arguments and environment variables.
+
+use std::rc::Rc;
+
+use super::{i32_argument, string_argument, FFIType, FFIValue, HostFunctions};
+
+pub fn register(functions: &mut HostFunctions, args: &[String], allow_env: bool) {
+    let args = Rc::new(args.to_vec());
+
+    let argc_args = args.clone();
+    functions.register("argc", vec![], FFIType::I32, move |_, _| Ok(FFIValue::I32(argc_args.len() as i32)));
+
+    // A null pointer if there is no argument at the index.
+    functions.register("argv", vec![FFIType::I32], FFIType::String, move |_, arguments| {
+        let index = i32_argument(&arguments, 0)?;
+        match usize::try_from(index).ok().and_then(|index| args.get(index)) {
+            Some(arg) => Ok(FFIValue::String(arg.clone())),
+            None => Ok(FFIValue::Null),
+        }
+    });
+
+    // A null pointer if the variable is not set or the host does not allow reading it.
+    functions.register("getenv", vec![FFIType::String], FFIType::String, move |_, arguments| {
+        let name = string_argument(&arguments, 0)?;
+        match std::env::var(name) {
+            Ok(value) if allow_env => Ok(FFIValue::String(value)),
+            _ => Ok(FFIValue::Null),
+        }
+    });
+}
@@ -0,0 +1,230 @@
+//! File system access, limited to the directories the host allows.
+//!
+//! Functions that return an integer report failures with one of the negative `ERROR_` codes.
+//! Functions that return a string return a null pointer instead.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::{i32_argument, string_argument, FFIType, FFIValue, HostFunctions};
+
+pub const ERROR_NOT_ALLOWED: i32 = -1;
+pub const ERROR_NOT_FOUND: i32 = -2;
+pub const ERROR_BAD_HANDLE: i32 = -3;
+pub const ERROR_IO: i32 = -4;
+pub const ERROR_INVALID_ARGUMENT: i32 = -5;
+
+pub const MODE_READ: i32 = 0;
+pub const MODE_WRITE: i32 = 1;
+pub const MODE_APPEND: i32 = 2;
+
+/// The directories files may be read from and written to, including their subdirectories.
+struct Sandbox {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    fn new(read: &[PathBuf], write: &[PathBuf]) -> Self {
+        // Directories that do not exist cannot contain anything to grant access to.
+        let canonicalize = |directories: &[PathBuf]| {
+            directories.iter().filter_map(|directory| directory.canonicalize().ok()).collect()
+        };
+        Self {
+            read: canonicalize(read),
+            write: canonicalize(write),
+        }
+    }
+
+    fn check_read(&self, path: &str) -> Result<PathBuf, i32> {
+        Self::check(&self.read, path)
+    }
+
+    fn check_write(&self, path: &str) -> Result<PathBuf, i32> {
+        Self::check(&self.write, path)
+    }
+
+    fn check(directories: &[PathBuf], path: &str) -> Result<PathBuf, i32> {
+        let path = resolve(Path::new(path)).map_err(error_code)?;
+        if directories.iter().any(|directory| path.starts_with(directory)) {
+            Ok(path)
+        } else {
+            Err(ERROR_NOT_ALLOWED)
+        }
+    }
+}
+
+/// The absolute path without `..` or symbolic links, also for files that do not exist yet.
+/// A symbolic link whose target does not exist is rejected, since creating the file would follow
+/// it to wherever it points.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            if fs::symlink_metadata(path).is_ok() {
+                return Err(ErrorKind::PermissionDenied.into());
+            }
+            let name = path.file_name().ok_or(e)?;
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            Ok(parent.canonicalize()?.join(name))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn error_code(error: io::Error) -> i32 {
+    match error.kind() {
+        ErrorKind::NotFound => ERROR_NOT_FOUND,
+        ErrorKind::PermissionDenied => ERROR_NOT_ALLOWED,
+        _ => ERROR_IO,
+    }
+}
+
+#[derive(Default)]
+struct Files {
+    open: HashMap<i32, File>,
+    next_handle: i32,
+}
+
+impl Files {
+    fn insert(&mut self, file: File) -> i32 {
+        // Handles start after the ones of the standard streams.
+        let handle = self.next_handle + 3;
+        self.next_handle += 1;
+        self.open.insert(handle, file);
+        handle
+    }
+}
+
+fn open(sandbox: &Sandbox, path: &str, mode: i32) -> Result<File, i32> {
+    let mut options = OpenOptions::new();
+    let path = match mode {
+        MODE_READ => {
+            options.read(true);
+            sandbox.check_read(path)?
+        }
+        MODE_WRITE => {
+            options.write(true).create(true).truncate(true);
+            sandbox.check_write(path)?
+        }
+        MODE_APPEND => {
+            options.append(true).create(true);
+            sandbox.check_write(path)?
+        }
+        _ => return Err(ERROR_INVALID_ARGUMENT),
+    };
+    no_follow(&mut options);
+    options.open(path).map_err(error_code)
+}
+
+/// Makes opening fail if the file has become a symbolic link since the sandbox checked its path.
+fn no_follow(options: &mut OpenOptions) {
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(options, libc::O_NOFOLLOW);
+}
+
+fn code(result: Result<i32, i32>) -> FFIValue {
+    FFIValue::I32(result.unwrap_or_else(|code| code))
+}
+
+fn string_or_null(result: Result<String, i32>) -> FFIValue {
+    match result {
+        Ok(string) => FFIValue::String(string),
+        Err(_) => FFIValue::Null,
+    }
+}
+
+pub fn register(functions: &mut HostFunctions, allow_read: &[PathBuf], allow_write: &[PathBuf]) {
+    let sandbox = Rc::new(Sandbox::new(allow_read, allow_write));
+    let files = Rc::new(RefCell::new(Files::default()));
+
+    let (open_sandbox, open_files) = (sandbox.clone(), files.clone());
+    functions.register("open", vec![FFIType::String, FFIType::I32], FFIType::I32, move |_, arguments| {
+        let path = string_argument(&arguments, 0)?;
+        let mode = i32_argument(&arguments, 1)?;
+        Ok(code(open(&open_sandbox, path, mode).map(|file| open_files.borrow_mut().insert(file))))
+    });
+
+    // Reads up to `count` bytes into `buffer` and returns how many were read, 0 at the end.
+    let read_files = files.clone();
+    functions.register("read", vec![FFIType::I32, FFIType::I32, FFIType::I32], FFIType::I32, move |context, arguments| {
+        let handle = i32_argument(&arguments, 0)?;
+        let buffer = i32_argument(&arguments, 1)?;
+        let count = i32_argument(&arguments, 2)?;
+        let mut files = read_files.borrow_mut();
+        let Some(file) = files.open.get_mut(&handle) else {
+            return Ok(FFIValue::I32(ERROR_BAD_HANDLE));
+        };
+        let (Ok(buffer), Ok(count)) = (usize::try_from(buffer), usize::try_from(count)) else {
+            return Ok(FFIValue::I32(ERROR_INVALID_ARGUMENT));
+        };
+        context.memory.check_store(buffer, count)?;
+        let mut data = vec![0; count];
+        let read = match file.read(&mut data) {
+            Ok(read) => read,
+            Err(e) => return Ok(FFIValue::I32(error_code(e))),
+        };
+        context.memory.store(buffer, &data[..read])?;
+        Ok(FFIValue::I32(read as i32))
+    });
+
+    // Writes `count` bytes from `buffer` and returns how many were written.
+    let write_files = files.clone();
+    functions.register("write", vec![FFIType::I32, FFIType::I32, FFIType::I32], FFIType::I32, move |context, arguments| {
+        let handle = i32_argument(&arguments, 0)?;
+        let buffer = i32_argument(&arguments, 1)?;
+        let count = i32_argument(&arguments, 2)?;
+        let mut files = write_files.borrow_mut();
+        let Some(file) = files.open.get_mut(&handle) else {
+            return Ok(FFIValue::I32(ERROR_BAD_HANDLE));
+        };
+        let (Ok(buffer), Ok(count)) = (usize::try_from(buffer), usize::try_from(count)) else {
+            return Ok(FFIValue::I32(ERROR_INVALID_ARGUMENT));
+        };
+        let data = context.memory.load(buffer, count)?;
+        Ok(code(file.write_all(data).map(|_| count as i32).map_err(error_code)))
+    });
+
+    let close_files = files;
+    functions.register("close", vec![FFIType::I32], FFIType::I32, move |_, arguments| {
+        let handle = i32_argument(&arguments, 0)?;
+        match close_files.borrow_mut().open.remove(&handle) {
+            Some(_) => Ok(FFIValue::I32(0)),
+            None => Ok(FFIValue::I32(ERROR_BAD_HANDLE)),
+        }
+    });
+
+    let read_file_sandbox = sandbox.clone();
+    functions.register("read_file", vec![FFIType::String], FFIType::String, move |_, arguments| {
+        let path = string_argument(&arguments, 0)?;
+        Ok(string_or_null(read_file_sandbox.check_read(path).and_then(|path| fs::read_to_string(path).map_err(error_code))))
+    });
+
+    let write_file_sandbox = sandbox.clone();
+    functions.register("write_file", vec![FFIType::String, FFIType::String], FFIType::I32, move |_, arguments| {
+        let path = string_argument(&arguments, 0)?;
+        let contents = string_argument(&arguments, 1)?;
+        let write = |mut file: File| file.write_all(contents.as_bytes()).map(|_| 0).map_err(error_code);
+        Ok(code(open(&write_file_sandbox, path, MODE_WRITE).and_then(write)))
+    });
+
+    // The names of the entries of a directory, sorted and separated by line breaks.
+    let list_dir_sandbox = sandbox;
+    functions.register("list_dir", vec![FFIType::String], FFIType::String, move |_, arguments| {
+        let path = string_argument(&arguments, 0)?;
+        Ok(string_or_null(list_dir_sandbox.check_read(path).and_then(|path| {
+            let mut names = fs::read_dir(path)
+                .and_then(|entries| entries.map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned())).collect::<io::Result<Vec<_>>>())
+                .map_err(error_code)?;
+            names.sort();
+            Ok(names.join("\n"))
+        })))
+    });
+}
@@ -0,0 +1,59 @@
+//! Console input and output.
+
+use std::io::{self, BufRead, Write};
+
+use super::{i32_argument, string_argument, FFIType, FFIValue, HostFunctions};
+
+pub fn register(functions: &mut HostFunctions) {
+    // Byte code without an import table calls println as function 0.
+    functions.register("println", vec![FFIType::String], FFIType::Void, |_, arguments| {
+        println!("{}", string_argument(&arguments, 0)?);
+        Ok(FFIValue::Void)
+    });
+    functions.register("print", vec![FFIType::String], FFIType::Void, |_, arguments| {
+        print!("{}", string_argument(&arguments, 0)?);
+        Ok(FFIValue::Void)
+    });
+    functions.register("eprint", vec![FFIType::String], FFIType::Void, |_, arguments| {
+        eprint!("{}", string_argument(&arguments, 0)?);
+        Ok(FFIValue::Void)
+    });
+    functions.register("eprintln", vec![FFIType::String], FFIType::Void, |_, arguments| {
+        eprintln!("{}", string_argument(&arguments, 0)?);
+        Ok(FFIValue::Void)
+    });
+    functions.register("flush", vec![], FFIType::Void, |_, _| {
+        flush()?;
+        Ok(FFIValue::Void)
+    });
+    // Returns the next line without its line break, or an empty string at the end of the input.
+    functions.register("read_line", vec![], FFIType::String, |_, _| {
+        Ok(FFIValue::String(read_line()?.unwrap_or_default()))
+    });
+    functions.register("read_int", vec![], FFIType::I32, |_, _| {
+        let line = read_line()?.ok_or("read_int: Reached the end of the input")?;
+        let value = line.trim().parse().map_err(|_| format!("read_int: {:?} is not an integer", line))?;
+        Ok(FFIValue::I32(value))
+    });
+    functions.register("exit", vec![FFIType::I32], FFIType::Void, |context, arguments| {
+        context.exit_code = Some(i32_argument(&arguments, 0)?);
+        Ok(FFIValue::Void)
+    });
+}
+
+fn flush() -> Result<(), String> {
+    io::stdout().flush().map_err(|e| e.to_string())
+}
+
+fn read_line() -> Result<Option<String>, String> {
+    // Make prompts printed with `print` visible before blocking.
+    flush()?;
+    let mut line = String::new();
+    let read = io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())?;
+    if read == 0 {
+        return Ok(None);
+    }
+    let length = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(length);
+    Ok(Some(line))
+}
@@ -0,0 +1,67 @@
+//! Integer math, random numbers and the clock.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{i32_argument, FFIType, FFIValue, HostFunctions};
+
+pub fn register(functions: &mut HostFunctions, seed: Option<u64>) {
+    functions.register("abs", vec![FFIType::I32], FFIType::I32, |_, arguments| {
+        Ok(FFIValue::I32(i32_argument(&arguments, 0)?.wrapping_abs()))
+    });
+    functions.register("min", vec![FFIType::I32, FFIType::I32], FFIType::I32, |_, arguments| {
+        Ok(FFIValue::I32(i32_argument(&arguments, 0)?.min(i32_argument(&arguments, 1)?)))
+    });
+    functions.register("max", vec![FFIType::I32, FFIType::I32], FFIType::I32, |_, arguments| {
+        Ok(FFIValue::I32(i32_argument(&arguments, 0)?.max(i32_argument(&arguments, 1)?)))
+    });
+    functions.register("pow", vec![FFIType::I32, FFIType::I32], FFIType::I32, |_, arguments| {
+        let base = i32_argument(&arguments, 0)?;
+        let exponent = i32_argument(&arguments, 1)?;
+        let exponent = u32::try_from(exponent).map_err(|_| format!("pow: Negative exponent {}", exponent))?;
+        Ok(FFIValue::I32(base.wrapping_pow(exponent)))
+    });
+    // The integer square root, rounded down.
+    functions.register("sqrt", vec![FFIType::I32], FFIType::I32, |_, arguments| {
+        let value = i32_argument(&arguments, 0)?;
+        let value = u32::try_from(value).map_err(|_| format!("sqrt: Negative argument {}", value))?;
+        Ok(FFIValue::I32(value.isqrt() as i32))
+    });
+    functions.register("time_millis", vec![], FFIType::I64, |_, _| {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?;
+        Ok(FFIValue::I64(elapsed.as_millis() as i64))
+    });
+    let mut random = Random::new(seed.unwrap_or_else(clock_seed));
+    // A number in `lowerBound..upperBound`, like `random` of the Kotlin evaluator.
+    functions.register("random", vec![FFIType::I32, FFIType::I32], FFIType::I32, move |_, arguments| {
+        let lower = i32_argument(&arguments, 0)?;
+        let upper = i32_argument(&arguments, 1)?;
+        if upper <= lower {
+            return Err(format!("random: Empty range {}..{}", lower, upper));
+        }
+        let range = (upper as i64 - lower as i64) as u64;
+        Ok(FFIValue::I32((lower as i64 + (random.next() % range) as i64) as i32))
+    });
+}
+
+fn clock_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_nanos() as u64).unwrap_or(0)
+}
+
+/// SplitMix64, which is small and gives the same sequence for a seed on every platform.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
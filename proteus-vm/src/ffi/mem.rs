@@ -0,0 +1,51 @@
+//! Heap allocation for programs that manage memory through host functions.
+
+use super::{i32_argument, string_argument, FFIType, FFIValue, HostFunctions};
+
+pub fn register(functions: &mut HostFunctions) {
+    functions.register("malloc", vec![FFIType::I32], FFIType::I32, |context, arguments| {
+        let size = size_argument(&arguments, 0)?;
+        Ok(FFIValue::I32(context.memory.allocate_heap(size)? as i32))
+    });
+    // Like malloc, but for `count` elements of `size` bytes that are all zero.
+    functions.register("calloc", vec![FFIType::I32, FFIType::I32], FFIType::I32, |context, arguments| {
+        let count = size_argument(&arguments, 0)?;
+        let size = size_argument(&arguments, 1)?;
+        let bytes = count.checked_mul(size).ok_or("calloc: Size overflows")?;
+        Ok(FFIValue::I32(context.memory.allocate_heap_data(&vec![0; bytes])? as i32))
+    });
+    // Resizes an allocation, moving it if needed. A null pointer allocates a new one.
+    functions.register("realloc", vec![FFIType::I32, FFIType::I32], FFIType::I32, |context, arguments| {
+        let pointer = i32_argument(&arguments, 0)?;
+        let size = size_argument(&arguments, 1)?;
+        let pointer = match pointer {
+            0 => context.memory.allocate_heap(size)?,
+            pointer => context.memory.reallocate_heap(pointer as usize, size)?,
+        };
+        Ok(FFIValue::I32(pointer as i32))
+    });
+    // Freeing a null pointer does nothing.
+    functions.register("free", vec![FFIType::I32], FFIType::Void, |context, arguments| {
+        match i32_argument(&arguments, 0)? {
+            0 => {}
+            pointer => context.memory.free_allocation(pointer as usize)?,
+        }
+        Ok(FFIValue::Void)
+    });
+}
+
+fn size_argument(arguments: &[FFIValue], index: usize) -> Result<usize, String> {
+    let size = i32_argument(arguments, index)?;
+    usize::try_from(size).map_err(|_| format!("Negative size {}", size))
+}
+
+/// Registered after all other standard functions, so that the indices of the older ones stay the
+/// same for byte code without an import table.
+pub fn register_snapshot(functions: &mut HostFunctions) {
+    // Records a snapshot of the live heap allocations under the given label, see `--heap-snapshot`.
+    functions.register("heap_snapshot", vec![FFIType::String], FFIType::Void, |context, arguments| {
+        let label = string_argument(&arguments, 0)?;
+        context.memory.take_snapshot(label);
+        Ok(FFIValue::Void)
+    });
+}
@@ -1,56 +1,197 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::memory::Memory;
+
+pub mod array;
+pub mod env;
+pub mod fs;
+pub mod io;
+pub mod math;
+pub mod mem;
+#[cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub mod native;
+
+/// What a host function gets to see of the VM while it runs.
+pub struct HostContext<'a> {
+    pub memory: &'a mut Memory,
+    /// Set to stop the VM after the call returns, with the given process exit code.
+    pub exit_code: Option<i32>,
+}
+
+pub type HostFunctionBody = Box<dyn FnMut(&mut HostContext, Vec<FFIValue>) -> Result<FFIValue, String>>;
+
 pub struct FFIFunction {
     pub name: String,
     pub arguments: Vec<FFIType>,
     pub return_type: FFIType,
+    body: HostFunctionBody,
 }
 
 impl FFIFunction {
-    pub fn new(name: &str, arguments: Vec<FFIType>, return_type: FFIType) -> Self {
+    pub fn new(name: &str, arguments: Vec<FFIType>, return_type: FFIType, body: HostFunctionBody) -> Self {
         Self {
             name: name.to_string(),
             arguments,
             return_type,
+            body,
         }
     }
 
-    pub fn println() -> Self {
-        Self::new("println", vec![FFIType::String], FFIType::Void)
+    pub fn call(&mut self, context: &mut HostContext, arguments: Vec<FFIValue>) -> Result<FFIValue, String> {
+        (self.body)(context, arguments)
     }
+}
 
-    pub fn find(index: &usize) -> Option<Self> {
-        match index {
-            0 => Some(Self::println()),
-            _ => None,
-        }
+/// Settings for the functions of `HostFunctions::standard`.
+#[derive(Debug, Clone, Default)]
+pub struct StandardOptions {
+    /// Seed of the random number generator. Seeded from the clock if not set.
+    pub seed: Option<u64>,
+    /// Directories whose files may be read. No file can be read if empty.
+    pub allow_read: Vec<PathBuf>,
+    /// Directories whose files may be written. No file can be written if empty.
+    pub allow_write: Vec<PathBuf>,
+    /// The arguments passed to the program.
+    pub args: Vec<String>,
+    /// Whether the program may read environment variables.
+    pub allow_env: bool,
+    /// Whether the program may load the native functions it declares, see `HostFunctions::link`.
+    pub allow_native: bool,
+}
+
+/// The host functions a program can import with `ffcall`.
+#[derive(Default)]
+pub struct HostFunctions {
+    functions: Vec<FFIFunction>,
+    indices: HashMap<String, usize>,
+    allow_native: bool,
+}
+
+impl HostFunctions {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn get_index(name: &str) -> Option<usize> {
-        match name {
-            "println" => Some(0),
-            _ => None,
+    /// The functions available to every program run by the command line.
+    pub fn standard() -> Self {
+        Self::with_options(&StandardOptions::default())
+    }
+
+    pub fn with_options(options: &StandardOptions) -> Self {
+        let mut functions = Self::new();
+        io::register(&mut functions);
+        math::register(&mut functions, options.seed);
+        mem::register(&mut functions);
+        fs::register(&mut functions, &options.allow_read, &options.allow_write);
+        env::register(&mut functions, &options.args, options.allow_env);
+        array::register(&mut functions);
+        mem::register_snapshot(&mut functions);
+        functions.allow_native = options.allow_native;
+        functions
+    }
+
+    /// Lets `link` load the native functions programs declare in their imports.
+    pub fn allow_native(&mut self, allow: bool) -> &mut Self {
+        self.allow_native = allow;
+        self
+    }
+
+    /// Finds the function a program imports as `import`, or `None` if there is none. An import
+    /// that declares a native function, like `add=libmath.so(i32,i32)->i32`, is loaded and
+    /// registered under that name, if native functions are allowed.
+    pub fn link(&mut self, import: &str) -> Result<Option<usize>, String> {
+        if let Some(index) = self.get_index(import) {
+            return Ok(Some(index));
+        }
+        if !import.contains('=') {
+            return Ok(None);
         }
+        if !self.allow_native {
+            return Err(format!("Native function {} is not allowed", import));
+        }
+        self.link_native(import)
+    }
+
+    #[cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn link_native(&mut self, import: &str) -> Result<Option<usize>, String> {
+        let native = import.parse::<native::NativeImport>()?;
+        self.register_native(&native::NativeImport { name: import.to_string(), ..native })?;
+        Ok(self.get_index(import))
+    }
+
+    #[cfg(not(all(unix, any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    fn link_native(&mut self, import: &str) -> Result<Option<usize>, String> {
+        Err(format!("Native function {} is not supported on this platform", import))
     }
 
-    pub fn call(&self, arguments: Vec<FFIValue>) -> Result<FFIValue, String> {
-        match self.name.as_str() {
-            "println" => {
-                let output = arguments.get(0).ok_or("Missing argument")?;
-                let output = match output {
-                    FFIValue::String(string) => string,
-                    _ => return Err("Invalid argument".to_string()),
-                };
-                println!("{}", output);
-                Ok(FFIValue::Void)
+    /// Registers a function under `name`, replacing any function registered under the same name.
+    pub fn register<F>(&mut self, name: &str, arguments: Vec<FFIType>, return_type: FFIType, body: F) -> &mut Self
+    where
+        F: FnMut(&mut HostContext, Vec<FFIValue>) -> Result<FFIValue, String> + 'static,
+    {
+        let function = FFIFunction::new(name, arguments, return_type, Box::new(body));
+        match self.indices.get(name) {
+            Some(&index) => self.functions[index] = function,
+            None => {
+                self.indices.insert(name.to_string(), self.functions.len());
+                self.functions.push(function);
             }
-            _ => Err(format!("Unknown FFI function: {}", self.name)),
         }
+        self
+    }
+
+    pub fn get_index(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut FFIFunction> {
+        self.functions.get_mut(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
+
+pub fn i32_argument(arguments: &[FFIValue], index: usize) -> Result<i32, String> {
+    match arguments.get(index) {
+        Some(FFIValue::I32(value)) => Ok(*value),
+        Some(_) => Err(format!("Argument {} is not an integer", index)),
+        None => Err(format!("Missing argument {}", index)),
+    }
+}
+
+pub fn string_argument(arguments: &[FFIValue], index: usize) -> Result<&str, String> {
+    match arguments.get(index) {
+        Some(FFIValue::String(string)) => Ok(string),
+        Some(_) => Err(format!("Argument {} is not a string", index)),
+        None => Err(format!("Missing argument {}", index)),
     }
 }
 
+/// How values cross the boundary between the VM and the host.
+///
+/// Strings are passed as a pointer to NUL terminated bytes. A string argument that lies on the
+/// stack directly below its pointer is popped along with it. Byte buffers are passed as a length
+/// with the pointer on top. A `Buffer` is passed the same way, but the function also gets its
+/// address to write into it; it cannot be returned.
+///
+/// Returned strings and byte buffers are copied into a fresh heap allocation that belongs to the
+/// program, which frees it with `free` once done. Strings take their length plus one byte for the
+/// terminator. A function declared to return a string or a byte buffer may return
+/// `FFIValue::Null` instead, which reaches the program as a null pointer with a length of 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FFIType {
     I32,
     I64,
     String,
+    Bytes,
+    Buffer,
     Void,
 }
 
@@ -59,5 +200,10 @@ pub enum FFIValue {
     I32(i32),
     I64(i64),
     String(String),
+    Bytes(Vec<u8>),
+    /// A byte buffer of the program and its content when the function was called.
+    Buffer { address: usize, data: Vec<u8> },
     Void,
+    /// No string or byte buffer, e.g. because a file could not be read.
+    Null,
 }
@@ -0,0 +1,190 @@
+//! Calls into native shared libraries through the C ABI.
+//!
+//! Every argument is passed in an integer register, which covers integers, pointers and C strings
+//! on x86-64 and AArch64 as long as the function takes at most six of them and is not variadic.
+//! Strings are passed as a NUL terminated copy and byte buffers as a pointer to a copy followed by
+//! their length, so native code never sees VM memory. What the function writes into the copy of a
+//! `buffer` argument is copied back to the program when it returns.
+//!
+//! Programs declare the native functions they need in their imports, see `HostFunctions::link`,
+//! and the host makes more available with `register_native`.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use super::{FFIType, FFIValue, HostContext, HostFunctions};
+
+const MAX_ARGUMENTS: usize = 6;
+
+type NativeFunction = unsafe extern "C" fn(i64, i64, i64, i64, i64, i64) -> i64;
+
+/// A native function made available to programs under `name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeImport {
+    pub name: String,
+    /// The path of the shared library, or its name to search the default library paths.
+    pub library: String,
+    pub symbol: String,
+    pub arguments: Vec<FFIType>,
+    pub return_type: FFIType,
+}
+
+impl FromStr for NativeImport {
+    type Err = String;
+
+    /// Parses `name=library:symbol(i32,string)->i32`. Without `:symbol` the symbol is the name,
+    /// and without a return type the function returns nothing.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid native import {:?}, expected name=library:symbol(types)->type", spec);
+        let (name, rest) = spec.split_once('=').ok_or_else(invalid)?;
+        let (head, signature) = rest.split_once('(').ok_or_else(invalid)?;
+        let (arguments, return_type) = signature.split_once(')').ok_or_else(invalid)?;
+        let (library, symbol) = head.rsplit_once(':').unwrap_or((head, name));
+        let arguments = arguments
+            .split(',')
+            .map(str::trim)
+            .filter(|argument| !argument.is_empty())
+            .map(parse_type)
+            .collect::<Result<_, _>>()?;
+        let return_type = match return_type.trim().strip_prefix("->") {
+            Some(return_type) => parse_type(return_type.trim())?,
+            None if return_type.trim().is_empty() => FFIType::Void,
+            None => return Err(invalid()),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            library: library.to_string(),
+            symbol: symbol.to_string(),
+            arguments,
+            return_type,
+        })
+    }
+}
+
+fn parse_type(name: &str) -> Result<FFIType, String> {
+    match name {
+        "i32" => Ok(FFIType::I32),
+        "i64" => Ok(FFIType::I64),
+        "string" => Ok(FFIType::String),
+        "bytes" => Ok(FFIType::Bytes),
+        "buffer" => Ok(FFIType::Buffer),
+        "void" => Ok(FFIType::Void),
+        _ => Err(format!("Unknown type {:?}", name)),
+    }
+}
+
+/// An open shared library, closed once no function refers to it anymore.
+struct Library {
+    handle: *mut c_void,
+}
+
+impl Library {
+    fn open(path: &str) -> Result<Self, String> {
+        let path = CString::new(path).map_err(|e| e.to_string())?;
+        let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(last_error());
+        }
+        Ok(Self { handle })
+    }
+
+    fn symbol(&self, name: &str) -> Result<*mut c_void, String> {
+        let name = CString::new(name).map_err(|e| e.to_string())?;
+        let symbol = unsafe { libc::dlsym(self.handle, name.as_ptr()) };
+        if symbol.is_null() {
+            return Err(last_error());
+        }
+        Ok(symbol)
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
+
+fn last_error() -> String {
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        "Unknown dynamic linker error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+    }
+}
+
+impl HostFunctions {
+    /// Loads the library and registers its function, failing if either cannot be found.
+    pub fn register_native(&mut self, import: &NativeImport) -> Result<&mut Self, String> {
+        let registers: usize = import
+            .arguments
+            .iter()
+            .map(|argument| match argument {
+                FFIType::Bytes | FFIType::Buffer => 2,
+                FFIType::Void => 0,
+                _ => 1,
+            })
+            .sum();
+        if registers > MAX_ARGUMENTS {
+            return Err(format!("{}: Native functions take at most {} arguments", import.name, MAX_ARGUMENTS));
+        }
+        if matches!(import.return_type, FFIType::Bytes | FFIType::Buffer) {
+            return Err(format!("{}: Native functions cannot return byte buffers", import.name));
+        }
+        let library = Rc::new(Library::open(&import.library)?);
+        let symbol = library.symbol(&import.symbol)?;
+        let function: NativeFunction = unsafe { std::mem::transmute(symbol) };
+        let return_type = import.return_type;
+        Ok(self.register(&import.name, import.arguments.clone(), return_type, move |context, arguments| {
+            // Keeps the library loaded as long as the function is registered.
+            let _library = &library;
+            call(function, return_type, arguments, context)
+        }))
+    }
+}
+
+fn call(function: NativeFunction, return_type: FFIType, arguments: Vec<FFIValue>, context: &mut HostContext) -> Result<FFIValue, String> {
+    // The copies have to outlive the call.
+    let mut strings = Vec::new();
+    let mut buffers = Vec::new();
+    let mut writable = Vec::new();
+    let mut registers = Vec::with_capacity(MAX_ARGUMENTS);
+    for argument in arguments {
+        match argument {
+            FFIValue::I32(value) => registers.push(value as i64),
+            FFIValue::I64(value) => registers.push(value),
+            FFIValue::String(string) => {
+                let string = CString::new(string).map_err(|e| e.to_string())?;
+                registers.push(string.as_ptr() as i64);
+                strings.push(string);
+            }
+            FFIValue::Bytes(bytes) => {
+                registers.push(bytes.as_ptr() as i64);
+                registers.push(bytes.len() as i64);
+                buffers.push(bytes);
+            }
+            FFIValue::Buffer { address, mut data } => {
+                registers.push(data.as_mut_ptr() as i64);
+                registers.push(data.len() as i64);
+                writable.push((address, data));
+            }
+            FFIValue::Void | FFIValue::Null => {}
+        }
+    }
+    registers.resize(MAX_ARGUMENTS, 0);
+    let result = unsafe { function(registers[0], registers[1], registers[2], registers[3], registers[4], registers[5]) };
+    for (address, data) in writable {
+        context.memory.store(address, &data)?;
+    }
+    Ok(match return_type {
+        FFIType::I32 => FFIValue::I32(result as i32),
+        FFIType::I64 => FFIValue::I64(result),
+        FFIType::String if result == 0 => FFIValue::Null,
+        FFIType::String => {
+            let string = unsafe { CStr::from_ptr(result as *const c_char) };
+            FFIValue::String(string.to_string_lossy().into_owned())
+        }
+        FFIType::Bytes | FFIType::Buffer | FFIType::Void => FFIValue::Void,
+    })
+}
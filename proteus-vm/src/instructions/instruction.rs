@@ -1,6 +1,6 @@
 use crate::instructions::OpCode;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Instruction {
     pub opcode: OpCode,
     pub operand: i32,
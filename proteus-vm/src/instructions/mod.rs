@@ -1,8 +1,10 @@
+#![allow(clippy::upper_case_acronyms)]
+
 use std::mem::transmute;
 
 pub mod instruction;
 
-#[derive(EnumIndex, IndexEnum, Debug, EnumString)]
+#[derive(EnumIndex, IndexEnum, Debug, Clone, Copy, PartialEq, Eq, EnumString)]
 #[repr(u32)]
 pub enum OpCode {
     NOP = 0x00,
@@ -38,9 +40,16 @@ pub enum OpCode {
     LOADA = 0x60,
     RLOAD = 0x61,
     RSTORE = 0x62,
+    MEMCPY = 0x63,
+    MEMSET = 0x64,
+    MEMCMP = 0x65,
     PUSHSP = 0x70,
     HALLOC = 0x80,
     DHALLOC = 0x81,
+    GC = 0x82,
+    RETAIN = 0x83,
+    RELEASE = 0x84,
+    HREALLOC = 0x85,
     FFCALL = 0x90,
     ITOA = 0x91,
     BTOA = 0x92,
@@ -49,7 +58,9 @@ pub enum OpCode {
 
 
 impl OpCode {
-
+    /// # Safety
+    ///
+    /// `op_code` must be the value of one of the variants.
     pub unsafe fn from_op_code(op_code: u32) -> OpCode {
         transmute(op_code)
     }
@@ -0,0 +1,132 @@
+//! Register based intermediate representation.
+//!
+//! The stack byte code is lowered into instructions with explicit operands: immediates, 4 byte
+//! slots relative to the frame pointer and virtual registers. Values that would only be pushed to be
+//! popped again by the next instruction never touch the VM stack. Virtual registers only live
+//! within a basic block; at block boundaries the pending values are pushed onto the VM stack, so
+//! memory looks exactly like it would in the stack interpreter whenever control leaves a block.
+//! Instructions without a register form are kept as is and executed by the stack interpreter.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+use crate::evaluator::DivisionByZero;
+use crate::instructions::instruction::Instruction;
+
+pub use self::translator::translate;
+
+mod translator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Immediate(i32),
+    /// The 4 byte value at frame pointer + offset, and the byte code instruction that loaded it.
+    Local(i32, usize),
+    Register(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    /// The 4 byte slot at frame pointer + offset, and the byte code instruction that stores to it.
+    Local(i32, usize),
+    Register(u32),
+}
+
+impl From<Place> for Operand {
+    fn from(place: Place) -> Self {
+        match place {
+            Place::Local(offset, site) => Operand::Local(offset, site),
+            Place::Register(register) => Operand::Register(register),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Xor,
+}
+
+impl BinaryOp {
+    /// Applies the operation like the stack interpreter does, with `a` being the top of the stack.
+    /// Arithmetic wraps around on overflow.
+    pub fn apply(self, a: i32, b: i32) -> Result<i32, DivisionByZero> {
+        Ok(match self {
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div | BinaryOp::Mod if b == 0 => return Err(DivisionByZero),
+            BinaryOp::Div => a.wrapping_div(b),
+            BinaryOp::Mod => a.wrapping_rem(b),
+            BinaryOp::Eq => (a == b) as i32,
+            BinaryOp::Ne => (a != b) as i32,
+            BinaryOp::Lt => (a < b) as i32,
+            BinaryOp::Le => (a <= b) as i32,
+            BinaryOp::Gt => (a > b) as i32,
+            BinaryOp::Ge => (a >= b) as i32,
+            BinaryOp::And => a & b,
+            BinaryOp::Or => a | b,
+            BinaryOp::Xor => a ^ b,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum IrInstruction {
+    Move { destination: Place, source: Operand },
+    Binary { op: BinaryOp, destination: Place, a: Operand, b: Operand },
+    Not { destination: Place, source: Operand },
+    /// Pushes a 4 byte value onto the VM stack.
+    Push(Operand),
+    /// Pops a 4 byte value off the VM stack.
+    Pop(Place),
+    /// Jumps to the given byte code instruction.
+    Jump(usize),
+    JumpIfZero(Operand, usize),
+    JumpIfNotZero(Operand, usize),
+    /// An instruction executed by the stack interpreter.
+    Stack { instruction: Instruction, instruction_counter: usize },
+}
+
+pub struct IrProgram {
+    pub instructions: Vec<IrInstruction>,
+    /// The byte code instruction every IR instruction came from. Faults are reported there, or at
+    /// the instruction of the local they access, which is where the stack interpreter reports them.
+    pub sites: Vec<usize>,
+    /// The index of the first IR instruction of every byte code instruction.
+    pub entry_points: Vec<usize>,
+    /// IR instructions before which no value lives in a register, mapped to the byte code
+    /// instruction the stack interpreter would continue with. Execution can only be suspended here.
+    pub resume_points: HashMap<usize, usize>,
+    pub register_count: usize,
+}
+
+impl IrProgram {
+    pub fn index_of(&self, instruction_counter: usize) -> Result<usize, String> {
+        self.entry_points
+            .get(instruction_counter)
+            .copied()
+            .ok_or_else(|| format!("Instruction {} is outside of the program", instruction_counter))
+    }
+}
+
+impl Debug for IrProgram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            writeln!(f, "{:>5}: {:?}", index, instruction)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::instructions::instruction::Instruction;
+use crate::instructions::OpCode;
+use crate::loading::ByteCodeParser;
+
+use super::{BinaryOp, IrInstruction, IrProgram, Operand, Place};
+
+/// Lowers the stack byte code into the register IR.
+pub fn translate(byte_code_parser: &ByteCodeParser) -> Result<IrProgram, String> {
+    let count = byte_code_parser.instruction_count();
+    let mut instructions = Vec::with_capacity(count);
+    for index in 0..count {
+        instructions.push(byte_code_parser.instruction_at(index)?);
+    }
+    let leaders = find_leaders(&instructions);
+
+    let mut translator = Translator::default();
+    let mut entry_points = Vec::with_capacity(count);
+    let mut resume_points = HashMap::new();
+    for (index, instruction) in instructions.into_iter().enumerate() {
+        if leaders.contains(&index) {
+            translator.end_block();
+            resume_points.entry(translator.instructions.len()).or_insert(index);
+        }
+        entry_points.push(translator.instructions.len());
+        let operand = instruction.operand;
+        let size = instruction.offset;
+        match instruction.opcode {
+            OpCode::NOP => {}
+            OpCode::PUSH => translator.stack.push((Operand::Immediate(operand), index)),
+            OpCode::LOAD if size == 4 => translator.stack.push((Operand::Local(operand, index), index)),
+            OpCode::STORE if size == 4 => translator.store(operand, index),
+            OpCode::POP => {
+                translator.pop(index);
+            }
+            OpCode::IADD => translator.binary(BinaryOp::Add, index),
+            OpCode::ISUB => translator.binary(BinaryOp::Sub, index),
+            OpCode::IMUL => translator.binary(BinaryOp::Mul, index),
+            OpCode::IDIV => translator.binary(BinaryOp::Div, index),
+            OpCode::IMOD => translator.binary(BinaryOp::Mod, index),
+            OpCode::IEQ => translator.binary(BinaryOp::Eq, index),
+            OpCode::INE => translator.binary(BinaryOp::Ne, index),
+            OpCode::ILT => translator.binary(BinaryOp::Lt, index),
+            OpCode::ILE => translator.binary(BinaryOp::Le, index),
+            OpCode::IGT => translator.binary(BinaryOp::Gt, index),
+            OpCode::IGE => translator.binary(BinaryOp::Ge, index),
+            OpCode::IAND => translator.binary(BinaryOp::And, index),
+            OpCode::IOR => translator.binary(BinaryOp::Or, index),
+            OpCode::IXOR => translator.binary(BinaryOp::Xor, index),
+            OpCode::INOT => {
+                let source = translator.pop(index);
+                let destination = Place::Register(translator.new_register());
+                translator.emit(IrInstruction::Not { destination, source }, index);
+                translator.stack.push((destination.into(), index));
+            }
+            OpCode::JMP => {
+                translator.end_block();
+                translator.emit(IrInstruction::Jump(operand as usize), index);
+            }
+            OpCode::JZ | OpCode::JNZ => {
+                let condition = translator.pop(index);
+                translator.flush();
+                let jump = match instruction.opcode {
+                    OpCode::JZ => IrInstruction::JumpIfZero(condition, operand as usize),
+                    _ => IrInstruction::JumpIfNotZero(condition, operand as usize),
+                };
+                translator.emit(jump, index);
+            }
+            _ => {
+                translator.end_block();
+                // Entering here means the pending values are already on the VM stack.
+                entry_points[index] = translator.instructions.len();
+                resume_points.insert(translator.instructions.len(), index);
+                translator.emit(IrInstruction::Stack { instruction, instruction_counter: index }, index);
+            }
+        }
+    }
+    translator.end_block();
+    Ok(IrProgram {
+        instructions: translator.instructions,
+        sites: translator.sites,
+        entry_points,
+        resume_points,
+        register_count: translator.register_count,
+    })
+}
+
+/// Instructions that can be reached from somewhere other than the previous instruction.
+fn find_leaders(instructions: &[Instruction]) -> HashSet<usize> {
+    let mut leaders = HashSet::from([0]);
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction.opcode {
+            OpCode::JMP | OpCode::JZ | OpCode::JNZ => {
+                leaders.insert(instruction.operand as usize);
+            }
+            OpCode::CALL => {
+                leaders.insert(instruction.operand as usize);
+                // Returns land on the instruction after the call.
+                leaders.insert(index + 1);
+            }
+            _ => {}
+        }
+    }
+    leaders
+}
+
+#[derive(Default)]
+struct Translator {
+    instructions: Vec<IrInstruction>,
+    sites: Vec<usize>,
+    /// Values pushed in the current block that have not been written to the VM stack yet, with the
+    /// instruction that pushed them.
+    stack: Vec<(Operand, usize)>,
+    next_register: u32,
+    register_count: usize,
+}
+
+impl Translator {
+    fn new_register(&mut self) -> u32 {
+        let register = self.next_register;
+        self.next_register += 1;
+        self.register_count = self.register_count.max(self.next_register as usize);
+        register
+    }
+
+    /// Appends an IR instruction lowered from the byte code instruction at `site`.
+    fn emit(&mut self, instruction: IrInstruction, site: usize) {
+        self.instructions.push(instruction);
+        self.sites.push(site);
+    }
+
+    /// Pops a pending value, or emits a pop from the VM stack for the instruction at `site` if
+    /// there is none.
+    fn pop(&mut self, site: usize) -> Operand {
+        match self.stack.pop() {
+            Some((operand, _)) => operand,
+            None => {
+                let register = self.new_register();
+                self.emit(IrInstruction::Pop(Place::Register(register)), site);
+                Operand::Register(register)
+            }
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, site: usize) {
+        let a = self.pop(site);
+        let b = self.pop(site);
+        let destination = Place::Register(self.new_register());
+        self.emit(IrInstruction::Binary { op, destination, a, b }, site);
+        self.stack.push((destination.into(), site));
+    }
+
+    fn store(&mut self, offset: i32, site: usize) {
+        let source = self.pop(site);
+        // Pending loads overlapping the slot must observe the old value.
+        for index in 0..self.stack.len() {
+            if let (Operand::Local(loaded, load_site), pushed) = self.stack[index] {
+                if (loaded - offset).abs() >= 4 {
+                    continue;
+                }
+                let register = self.new_register();
+                let copy = IrInstruction::Move {
+                    destination: Place::Register(register),
+                    source: Operand::Local(loaded, load_site),
+                };
+                self.emit(copy, load_site);
+                self.stack[index] = (Operand::Register(register), pushed);
+            }
+        }
+        let destination = Place::Local(offset, site);
+        // Write the result of the previous instruction straight into the slot.
+        if let Operand::Register(register) = source {
+            match self.instructions.last_mut() {
+                Some(IrInstruction::Binary { destination: target, .. })
+                | Some(IrInstruction::Not { destination: target, .. })
+                | Some(IrInstruction::Move { destination: target, .. })
+                    if *target == Place::Register(register) =>
+                {
+                    *target = destination;
+                    return;
+                }
+                _ => {}
+            }
+        }
+        self.emit(IrInstruction::Move { destination, source }, site);
+    }
+
+    /// Writes all pending values to the VM stack, each at the instruction that pushed it.
+    fn flush(&mut self) {
+        for (operand, site) in std::mem::take(&mut self.stack) {
+            self.emit(IrInstruction::Push(operand), site);
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.flush();
+        self.next_register = 0;
+    }
+}
@@ -0,0 +1,295 @@
+/// A minimal x86-64 encoder covering the instructions the template compiler emits.
+///
+/// Memory operands always use a 32-bit displacement so every instruction has a fixed shape,
+/// which keeps the encoder small at the cost of a few bytes per access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Register {
+    Rax = 0,
+    Rcx = 1,
+    Rdx = 2,
+    Rbx = 3,
+    Rsp = 4,
+    Rsi = 6,
+    Rdi = 7,
+    R12 = 12,
+    R13 = 13,
+    R14 = 14,
+    R15 = 15,
+}
+
+impl Register {
+    fn low(self) -> u8 {
+        self as u8 & 7
+    }
+
+    fn extended(self) -> bool {
+        self as u8 >= 8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Condition {
+    Below = 0x2,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    BelowOrEqual = 0x6,
+    Above = 0x7,
+    Sign = 0x8,
+    Less = 0xC,
+    GreaterOrEqual = 0xD,
+    LessOrEqual = 0xE,
+    Greater = 0xF,
+}
+
+/// Two operand 32-bit ALU instructions in their `op r/m32, r32` form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AluOp {
+    Add = 0x01,
+    Or = 0x09,
+    And = 0x21,
+    Sub = 0x29,
+    Xor = 0x31,
+    Cmp = 0x39,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+pub struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            code: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    /// Resolves all jumps and returns the machine code.
+    pub fn finish(mut self) -> Result<Vec<u8>, String> {
+        for (position, label) in &self.fixups {
+            let target = self.labels[label.0].ok_or("Jump to unbound label")?;
+            let relative = target as i64 - (*position as i64 + 4);
+            let relative = i32::try_from(relative).map_err(|_| "Jump distance too large")?;
+            self.code[*position..*position + 4].copy_from_slice(&relative.to_le_bytes());
+        }
+        Ok(self.code)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn rex(&mut self, wide: bool, reg: bool, index: bool, base: bool) {
+        if wide || reg || index || base {
+            self.code.push(0x40 | (wide as u8) << 3 | (reg as u8) << 2 | (index as u8) << 1 | base as u8);
+        }
+    }
+
+    fn modrm_register(&mut self, reg: u8, rm: Register) {
+        self.code.push(0xC0 | (reg & 7) << 3 | rm.low());
+    }
+
+    /// Encodes `[base + index + displacement]` (or `[base + displacement]`) for the given reg field.
+    fn modrm_memory(&mut self, reg: u8, base: Register, index: Option<Register>, displacement: i32) {
+        match index {
+            Some(index) => {
+                self.code.push(0x80 | (reg & 7) << 3 | 0b100);
+                self.code.push(index.low() << 3 | base.low());
+            }
+            None => {
+                self.code.push(0x80 | (reg & 7) << 3 | base.low());
+                if base.low() == Register::Rsp.low() {
+                    self.code.push(0x24);
+                }
+            }
+        }
+        self.emit(&displacement.to_le_bytes());
+    }
+
+    fn memory_instruction(&mut self, wide: bool, opcode: &[u8], reg: u8, base: Register, index: Option<Register>, displacement: i32) {
+        self.rex(wide, reg >= 8, index.is_some_and(Register::extended), base.extended());
+        self.emit(opcode);
+        self.modrm_memory(reg, base, index, displacement);
+    }
+
+    pub fn push(&mut self, register: Register) {
+        self.rex(false, false, false, register.extended());
+        self.code.push(0x50 + register.low());
+    }
+
+    pub fn pop(&mut self, register: Register) {
+        self.rex(false, false, false, register.extended());
+        self.code.push(0x58 + register.low());
+    }
+
+    /// `mov dst, src` on 64-bit registers.
+    pub fn mov(&mut self, destination: Register, source: Register) {
+        self.rex(true, source.extended(), false, destination.extended());
+        self.code.push(0x89);
+        self.modrm_register(source as u8, destination);
+    }
+
+    /// `mov dst, imm64`
+    pub fn mov_immediate64(&mut self, destination: Register, value: u64) {
+        self.rex(true, false, false, destination.extended());
+        self.code.push(0xB8 + destination.low());
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// `mov dst32, imm32`, zero extending into the full register.
+    pub fn mov_immediate32(&mut self, destination: Register, value: u32) {
+        self.rex(false, false, false, destination.extended());
+        self.code.push(0xB8 + destination.low());
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// `mov dst, qword [base + displacement]`
+    pub fn load64(&mut self, destination: Register, base: Register, displacement: i32) {
+        self.memory_instruction(true, &[0x8B], destination as u8, base, None, displacement);
+    }
+
+    /// `mov qword [base + displacement], src`
+    pub fn store64(&mut self, base: Register, displacement: i32, source: Register) {
+        self.memory_instruction(true, &[0x89], source as u8, base, None, displacement);
+    }
+
+    /// `mov dst32, dword [base + index + displacement]`
+    pub fn load32(&mut self, destination: Register, base: Register, index: Register, displacement: i32) {
+        self.memory_instruction(false, &[0x8B], destination as u8, base, Some(index), displacement);
+    }
+
+    /// `mov dword [base + index + displacement], src32`
+    pub fn store32(&mut self, base: Register, index: Register, displacement: i32, source: Register) {
+        self.memory_instruction(false, &[0x89], source as u8, base, Some(index), displacement);
+    }
+
+    /// `mov dword [base + index + displacement], imm32`
+    pub fn store32_immediate(&mut self, base: Register, index: Register, displacement: i32, value: u32) {
+        self.memory_instruction(false, &[0xC7], 0, base, Some(index), displacement);
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// `mov byte [base + index + displacement], imm8`
+    pub fn store8_immediate(&mut self, base: Register, index: Register, displacement: i32, value: u8) {
+        self.memory_instruction(false, &[0xC6], 0, base, Some(index), displacement);
+        self.code.push(value);
+    }
+
+    /// `lea dst, [base + displacement]`
+    pub fn lea(&mut self, destination: Register, base: Register, displacement: i32) {
+        self.memory_instruction(true, &[0x8D], destination as u8, base, None, displacement);
+    }
+
+    /// `cmp left, right` on 64-bit registers.
+    pub fn cmp(&mut self, left: Register, right: Register) {
+        self.rex(true, right.extended(), false, left.extended());
+        self.code.push(0x39);
+        self.modrm_register(right as u8, left);
+    }
+
+    /// `cmp register, imm32` on a 64-bit register.
+    pub fn cmp_immediate(&mut self, register: Register, value: i32) {
+        self.immediate_group(7, register, value);
+    }
+
+    /// `add register, imm32` on a 64-bit register.
+    pub fn add_immediate(&mut self, register: Register, value: i32) {
+        self.immediate_group(0, register, value);
+    }
+
+    /// `sub register, imm32` on a 64-bit register.
+    pub fn sub_immediate(&mut self, register: Register, value: i32) {
+        self.immediate_group(5, register, value);
+    }
+
+    fn immediate_group(&mut self, extension: u8, register: Register, value: i32) {
+        self.rex(true, false, false, register.extended());
+        self.code.push(0x81);
+        self.modrm_register(extension, register);
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// `op dst32, src32`
+    pub fn alu32(&mut self, op: AluOp, destination: Register, source: Register) {
+        self.rex(false, source.extended(), false, destination.extended());
+        self.code.push(op as u8);
+        self.modrm_register(source as u8, destination);
+    }
+
+    /// `imul dst32, src32`
+    pub fn imul32(&mut self, destination: Register, source: Register) {
+        self.rex(false, destination.extended(), false, source.extended());
+        self.emit(&[0x0F, 0xAF]);
+        self.modrm_register(destination as u8, source);
+    }
+
+    /// `not register32`
+    pub fn not32(&mut self, register: Register) {
+        self.rex(false, false, false, register.extended());
+        self.code.push(0xF7);
+        self.modrm_register(2, register);
+    }
+
+    /// `bswap register32`
+    pub fn bswap32(&mut self, register: Register) {
+        self.rex(false, false, false, register.extended());
+        self.emit(&[0x0F, 0xC8 + register.low()]);
+    }
+
+    /// `test left32, right32`
+    pub fn test32(&mut self, left: Register, right: Register) {
+        self.rex(false, right.extended(), false, left.extended());
+        self.code.push(0x85);
+        self.modrm_register(right as u8, left);
+    }
+
+    /// `setcc al; movzx eax, al`
+    pub fn set_eax(&mut self, condition: Condition) {
+        self.emit(&[0x0F, 0x90 + condition as u8, 0xC0]);
+        self.emit(&[0x0F, 0xB6, 0xC0]);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xE9);
+        self.fixup(label);
+    }
+
+    pub fn jcc(&mut self, condition: Condition, label: Label) {
+        self.emit(&[0x0F, 0x80 + condition as u8]);
+        self.fixup(label);
+    }
+
+    fn fixup(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    /// `call register`
+    pub fn call(&mut self, register: Register) {
+        self.rex(false, false, false, register.extended());
+        self.code.push(0xFF);
+        self.modrm_register(2, register);
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xC3);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::instructions::instruction::Instruction;
+use crate::instructions::OpCode;
+use crate::loading::ByteCodeParser;
+use crate::memory::layout::STACK_START;
+
+use super::assembler::{AluOp, Assembler, Condition, Label, Register};
+use super::{call_function, execute_instruction, CONTEXT_FRAME_POINTER, CONTEXT_INSTRUCTION_COUNTER, CONTEXT_PEAK_STACK_POINTER, CONTEXT_STACK, CONTEXT_STACK_LENGTH, CONTEXT_STACK_POINTER, STATUS_BAIL, STATUS_RETURNED};
+
+/// Functions with more instructions than this are left to the interpreter.
+const MAX_FUNCTION_SIZE: usize = 4096;
+
+// Register assignment. All of these are callee saved in the System V ABI, so they survive calls
+// into the interpreter helpers.
+const STACK_POINTER: Register = Register::Rbx;
+const STACK: Register = Register::R12;
+const FRAME_POINTER: Register = Register::R13;
+const CONTEXT: Register = Register::R14;
+const STACK_LENGTH: Register = Register::R15;
+const SAVED_REGISTERS: [Register; 5] = [Register::Rbx, Register::R12, Register::R13, Register::R14, Register::R15];
+
+/// Compiles the function starting at `entry` into machine code.
+///
+/// The function consists of every instruction reachable from `entry` without following calls.
+pub fn compile(byte_code_parser: &ByteCodeParser, entry: u32) -> Result<Vec<u8>, String> {
+    let instructions = collect_function(byte_code_parser, entry)?;
+    let mut compiler = FunctionCompiler::new(&instructions);
+    compiler.prologue(entry);
+    for (index, instruction) in &instructions {
+        compiler.instruction(*index, instruction);
+    }
+    compiler.epilogue();
+    compiler.assembler.finish()
+}
+
+fn collect_function(byte_code_parser: &ByteCodeParser, entry: u32) -> Result<Vec<(u32, Instruction)>, String> {
+    let mut reachable = BTreeSet::new();
+    let mut work_list = vec![entry];
+    while let Some(index) = work_list.pop() {
+        if (index as usize) >= byte_code_parser.instruction_count() {
+            return Err(format!("Control flow leaves the program at instruction {}", index));
+        }
+        if !reachable.insert(index) {
+            continue;
+        }
+        if reachable.len() > MAX_FUNCTION_SIZE {
+            return Err("Function too large".to_string());
+        }
+        let instruction = byte_code_parser.instruction_at(index as usize)?;
+        match instruction.opcode {
+            OpCode::JMP => work_list.push(instruction.operand as u32),
+            OpCode::JZ | OpCode::JNZ => {
+                work_list.push(instruction.operand as u32);
+                work_list.push(index + 1);
+            }
+            OpCode::IRET | OpCode::HALT => {}
+            _ => work_list.push(index + 1),
+        }
+    }
+    reachable
+        .into_iter()
+        .map(|index| Ok((index, byte_code_parser.instruction_at(index as usize)?)))
+        .collect()
+}
+
+struct FunctionCompiler {
+    assembler: Assembler,
+    labels: HashMap<u32, Label>,
+    bails: Vec<(Label, u32)>,
+    exit: Label,
+    exit_without_sync: Label,
+}
+
+impl FunctionCompiler {
+    fn new(instructions: &[(u32, Instruction)]) -> Self {
+        let mut assembler = Assembler::new();
+        let labels = instructions.iter().map(|(index, _)| (*index, assembler.new_label())).collect();
+        let exit = assembler.new_label();
+        let exit_without_sync = assembler.new_label();
+        Self {
+            assembler,
+            labels,
+            bails: Vec::new(),
+            exit,
+            exit_without_sync,
+        }
+    }
+
+    fn prologue(&mut self, entry: u32) {
+        for register in SAVED_REGISTERS {
+            self.assembler.push(register);
+        }
+        self.assembler.mov(CONTEXT, Register::Rdi);
+        self.assembler.load64(FRAME_POINTER, CONTEXT, CONTEXT_FRAME_POINTER);
+        self.reload_stack();
+        let entry = self.labels[&entry];
+        self.assembler.jmp(entry);
+    }
+
+    fn epilogue(&mut self) {
+        for (label, index) in std::mem::take(&mut self.bails) {
+            self.assembler.bind(label);
+            self.assembler.mov_immediate32(Register::Rax, index);
+            self.assembler.store64(CONTEXT, CONTEXT_INSTRUCTION_COUNTER, Register::Rax);
+            self.assembler.mov_immediate32(Register::Rax, STATUS_BAIL);
+            self.assembler.jmp(self.exit);
+        }
+        self.assembler.bind(self.exit);
+        self.assembler.store64(CONTEXT, CONTEXT_STACK_POINTER, STACK_POINTER);
+        self.assembler.bind(self.exit_without_sync);
+        for register in SAVED_REGISTERS.iter().rev() {
+            self.assembler.pop(*register);
+        }
+        self.assembler.ret();
+    }
+
+    fn reload_stack(&mut self) {
+        self.assembler.load64(STACK, CONTEXT, CONTEXT_STACK);
+        self.assembler.load64(STACK_LENGTH, CONTEXT, CONTEXT_STACK_LENGTH);
+        self.assembler.load64(STACK_POINTER, CONTEXT, CONTEXT_STACK_POINTER);
+    }
+
+    /// Jumps to a stub that hands `index` back to the interpreter when `condition` holds.
+    fn bail_if(&mut self, condition: Condition, index: u32) {
+        let label = self.assembler.new_label();
+        self.bails.push((label, index));
+        self.assembler.jcc(condition, label);
+    }
+
+    fn check_pop(&mut self, bytes: i32, index: u32) {
+        self.assembler.cmp_immediate(STACK_POINTER, bytes);
+        self.bail_if(Condition::Below, index);
+    }
+
+    /// Leaves the stack pointer after pushing `bytes` bytes in rax if they fit, and records it if it
+    /// is the deepest one so far.
+    fn check_push(&mut self, bytes: i32, index: u32) {
+        self.assembler.lea(Register::Rax, STACK_POINTER, bytes);
+        self.assembler.cmp(Register::Rax, STACK_LENGTH);
+        self.bail_if(Condition::Above, index);
+        let shallower = self.assembler.new_label();
+        self.assembler.load64(Register::Rdx, CONTEXT, CONTEXT_PEAK_STACK_POINTER);
+        self.assembler.cmp(Register::Rax, Register::Rdx);
+        self.assembler.jcc(Condition::BelowOrEqual, shallower);
+        self.assembler.store64(CONTEXT, CONTEXT_PEAK_STACK_POINTER, Register::Rax);
+        self.assembler.bind(shallower);
+    }
+
+    /// Leaves the stack address `frame pointer + offset` in rax if `bytes` bytes fit there.
+    fn check_frame_access(&mut self, offset: i32, bytes: i32, index: u32) {
+        self.assembler.lea(Register::Rax, FRAME_POINTER, offset);
+        self.assembler.cmp_immediate(Register::Rax, 0);
+        self.bail_if(Condition::Sign, index);
+        self.assembler.lea(Register::Rdx, Register::Rax, bytes);
+        self.assembler.cmp(Register::Rdx, STACK_LENGTH);
+        self.bail_if(Condition::Above, index);
+    }
+
+    /// Pushes the native value in eax onto the VM stack. The caller must have checked the space.
+    fn push_eax(&mut self) {
+        self.assembler.bswap32(Register::Rax);
+        self.assembler.store32(STACK, STACK_POINTER, 0, Register::Rax);
+        self.assembler.add_immediate(STACK_POINTER, 4);
+    }
+
+    fn call_helper(&mut self, helper: u64) {
+        self.assembler.store64(CONTEXT, CONTEXT_STACK_POINTER, STACK_POINTER);
+        self.assembler.mov(Register::Rdi, CONTEXT);
+        self.assembler.mov_immediate64(Register::Rax, helper);
+        self.assembler.call(Register::Rax);
+        self.assembler.test32(Register::Rax, Register::Rax);
+        self.assembler.jcc(Condition::NotEqual, self.exit_without_sync);
+        self.reload_stack();
+    }
+
+    fn instruction(&mut self, index: u32, instruction: &Instruction) {
+        let label = self.labels[&index];
+        self.assembler.bind(label);
+        let operand = instruction.operand;
+        match instruction.opcode {
+            OpCode::NOP => {}
+            OpCode::PUSH => {
+                self.check_push(4, index);
+                self.assembler.store32_immediate(STACK, STACK_POINTER, 0, (operand as u32).swap_bytes());
+                self.assembler.add_immediate(STACK_POINTER, 4);
+            }
+            OpCode::PUSHB => {
+                self.check_push(1, index);
+                self.assembler.store8_immediate(STACK, STACK_POINTER, 0, operand as u8);
+                self.assembler.add_immediate(STACK_POINTER, 1);
+            }
+            OpCode::POP => {
+                self.check_pop(4, index);
+                self.assembler.sub_immediate(STACK_POINTER, 4);
+            }
+            OpCode::ALLOC => {
+                self.check_push(operand, index);
+                self.assembler.mov(STACK_POINTER, Register::Rax);
+            }
+            OpCode::LOAD if instruction.offset == 4 => {
+                self.check_push(4, index);
+                self.check_frame_access(operand, 4, index);
+                self.assembler.load32(Register::Rcx, STACK, Register::Rax, 0);
+                self.assembler.store32(STACK, STACK_POINTER, 0, Register::Rcx);
+                self.assembler.add_immediate(STACK_POINTER, 4);
+            }
+            OpCode::STORE if instruction.offset == 4 => {
+                self.check_pop(4, index);
+                self.check_frame_access(operand, 4, index);
+                self.assembler.load32(Register::Rcx, STACK, STACK_POINTER, -4);
+                self.assembler.sub_immediate(STACK_POINTER, 4);
+                self.assembler.store32(STACK, Register::Rax, 0, Register::Rcx);
+            }
+            OpCode::LOADA => {
+                self.check_push(4, index);
+                self.assembler.lea(Register::Rax, FRAME_POINTER, operand + STACK_START as i32);
+                self.push_eax();
+            }
+            OpCode::PUSHSP => {
+                self.check_push(4, index);
+                self.assembler.lea(Register::Rax, STACK_POINTER, operand + STACK_START as i32);
+                self.push_eax();
+            }
+            OpCode::IADD => self.binary(index, |assembler| assembler.alu32(AluOp::Add, Register::Rax, Register::Rcx)),
+            OpCode::ISUB => self.binary(index, |assembler| assembler.alu32(AluOp::Sub, Register::Rax, Register::Rcx)),
+            OpCode::IMUL => self.binary(index, |assembler| assembler.imul32(Register::Rax, Register::Rcx)),
+            OpCode::IAND => self.binary(index, |assembler| assembler.alu32(AluOp::And, Register::Rax, Register::Rcx)),
+            OpCode::IOR => self.binary(index, |assembler| assembler.alu32(AluOp::Or, Register::Rax, Register::Rcx)),
+            OpCode::IXOR => self.binary(index, |assembler| assembler.alu32(AluOp::Xor, Register::Rax, Register::Rcx)),
+            OpCode::IEQ => self.comparison(index, Condition::Equal),
+            OpCode::INE => self.comparison(index, Condition::NotEqual),
+            OpCode::ILT => self.comparison(index, Condition::Less),
+            OpCode::ILE => self.comparison(index, Condition::LessOrEqual),
+            OpCode::IGT => self.comparison(index, Condition::Greater),
+            OpCode::IGE => self.comparison(index, Condition::GreaterOrEqual),
+            OpCode::INOT => {
+                // Bitwise not commutes with the byte swap, so the value can stay big endian.
+                self.check_pop(4, index);
+                self.assembler.load32(Register::Rax, STACK, STACK_POINTER, -4);
+                self.assembler.not32(Register::Rax);
+                self.assembler.store32(STACK, STACK_POINTER, -4, Register::Rax);
+            }
+            OpCode::JMP => {
+                let target = self.labels[&(operand as u32)];
+                self.assembler.jmp(target);
+            }
+            OpCode::JZ | OpCode::JNZ => {
+                self.check_pop(4, index);
+                self.assembler.load32(Register::Rax, STACK, STACK_POINTER, -4);
+                self.assembler.sub_immediate(STACK_POINTER, 4);
+                self.assembler.test32(Register::Rax, Register::Rax);
+                let condition = match instruction.opcode {
+                    OpCode::JZ => Condition::Equal,
+                    _ => Condition::NotEqual,
+                };
+                let target = self.labels[&(operand as u32)];
+                self.assembler.jcc(condition, target);
+            }
+            OpCode::CALL => {
+                self.assembler.mov_immediate32(Register::Rsi, index);
+                self.assembler.mov_immediate32(Register::Rdx, operand as u32);
+                self.call_helper(call_function as *const () as u64);
+            }
+            OpCode::IRET => {
+                self.assembler.mov_immediate32(Register::Rsi, index);
+                self.call_helper(execute_instruction as *const () as u64);
+                self.assembler.mov_immediate32(Register::Rax, STATUS_RETURNED);
+                self.assembler.jmp(self.exit_without_sync);
+            }
+            OpCode::HALT => {
+                let label = self.assembler.new_label();
+                self.bails.push((label, index));
+                self.assembler.jmp(label);
+            }
+            _ => {
+                self.assembler.mov_immediate32(Register::Rsi, index);
+                self.call_helper(execute_instruction as *const () as u64);
+            }
+        }
+    }
+
+    /// Pops `a` (top) into eax and `b` into ecx, applies `operation` and pushes eax.
+    fn binary(&mut self, index: u32, operation: impl FnOnce(&mut Assembler)) {
+        self.check_pop(8, index);
+        self.assembler.load32(Register::Rax, STACK, STACK_POINTER, -4);
+        self.assembler.bswap32(Register::Rax);
+        self.assembler.load32(Register::Rcx, STACK, STACK_POINTER, -8);
+        self.assembler.bswap32(Register::Rcx);
+        operation(&mut self.assembler);
+        self.assembler.bswap32(Register::Rax);
+        self.assembler.store32(STACK, STACK_POINTER, -8, Register::Rax);
+        self.assembler.sub_immediate(STACK_POINTER, 4);
+    }
+
+    fn comparison(&mut self, index: u32, condition: Condition) {
+        self.binary(index, |assembler| {
+            assembler.alu32(AluOp::Cmp, Register::Rax, Register::Rcx);
+            assembler.set_eax(condition);
+        });
+    }
+}
@@ -0,0 +1,53 @@
+use std::ptr;
+
+/// A page aligned region of memory holding compiled code.
+///
+/// The code is copied in while the region is writable and the region is then remapped as
+/// read + execute, so it is never writable and executable at the same time.
+pub struct ExecutableMemory {
+    pointer: *mut u8,
+    size: usize,
+}
+
+impl ExecutableMemory {
+    pub fn new(code: &[u8]) -> Result<Self, String> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = code.len().div_ceil(page_size).max(1) * page_size;
+        let pointer = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if pointer == libc::MAP_FAILED {
+            return Err("Could not map memory for compiled code".to_string());
+        }
+        let memory = Self {
+            pointer: pointer as *mut u8,
+            size,
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(code.as_ptr(), memory.pointer, code.len());
+            if libc::mprotect(pointer, size, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err("Could not make compiled code executable".to_string());
+            }
+        }
+        Ok(memory)
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.pointer
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.pointer as *mut libc::c_void, self.size);
+        }
+    }
+}
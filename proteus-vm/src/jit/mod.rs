@@ -0,0 +1,157 @@
+//! Baseline template JIT for x86-64 Linux.
+//!
+//! Functions that are called often enough are translated instruction by instruction into native
+//! code operating directly on the VM stack. Anything the templates do not cover (FFI calls, heap
+//! access, strings, calls and returns) is handed back to the interpreter through the helpers in this
+//! module, and any failing bounds check bails out to the interpreter at the faulting instruction,
+//! which then re-executes it and reports the error exactly like it would without the JIT.
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("The jit feature is only supported on x86-64 Linux");
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::evaluator::Evaluator;
+use crate::loading::ByteCodeParser;
+use crate::memory::layout::STACK_START;
+use crate::memory::Memory;
+
+use self::memory::ExecutableMemory;
+
+mod assembler;
+mod compiler;
+mod memory;
+
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+/// The helper finished and native execution continues with the next instruction.
+pub const STATUS_CONTINUE: u32 = 0;
+/// Native code stopped before executing `JitContext::instruction_counter`.
+pub const STATUS_BAIL: u32 = 1;
+/// The function returned; the interpreter continues at the return address.
+pub const STATUS_RETURNED: u32 = 2;
+pub const STATUS_HALTED: u32 = 3;
+pub const STATUS_ERROR: u32 = 4;
+
+pub type NativeFunction = unsafe extern "C" fn(*mut JitContext<'_>) -> u32;
+
+/// State shared between compiled code and the interpreter.
+///
+/// Compiled code only touches the leading fields, at the offsets exported below.
+#[repr(C)]
+pub struct JitContext<'a> {
+    pub stack: *mut u8,
+    pub stack_length: u64,
+    pub stack_pointer: u64,
+    pub frame_pointer: u64,
+    pub instruction_counter: u64,
+    /// The deepest stack pointer compiled code pushed to, for the memory statistics.
+    pub peak_stack_pointer: u64,
+    pub evaluator: *mut Evaluator<'a>,
+    pub error: Option<Box<dyn Error>>,
+}
+
+pub const CONTEXT_STACK: i32 = std::mem::offset_of!(JitContext<'static>, stack) as i32;
+pub const CONTEXT_STACK_LENGTH: i32 = std::mem::offset_of!(JitContext<'static>, stack_length) as i32;
+pub const CONTEXT_STACK_POINTER: i32 = std::mem::offset_of!(JitContext<'static>, stack_pointer) as i32;
+pub const CONTEXT_FRAME_POINTER: i32 = std::mem::offset_of!(JitContext<'static>, frame_pointer) as i32;
+pub const CONTEXT_INSTRUCTION_COUNTER: i32 = std::mem::offset_of!(JitContext<'static>, instruction_counter) as i32;
+pub const CONTEXT_PEAK_STACK_POINTER: i32 = std::mem::offset_of!(JitContext<'static>, peak_stack_pointer) as i32;
+
+impl<'a> JitContext<'a> {
+    /// `frame_pointer` is a VM address, compiled code works with offsets into the stack.
+    pub fn new(evaluator: *mut Evaluator<'a>, frame_pointer: u32) -> Self {
+        Self {
+            stack: std::ptr::null_mut(),
+            stack_length: 0,
+            stack_pointer: 0,
+            frame_pointer: (frame_pointer as usize - STACK_START) as u64,
+            instruction_counter: 0,
+            peak_stack_pointer: 0,
+            evaluator,
+            error: None,
+        }
+    }
+
+    /// Publishes the interpreter's view of the stack to compiled code.
+    pub fn sync_memory(&mut self, memory: &mut Memory) {
+        self.stack = memory.stack.as_mut_ptr();
+        self.stack_length = memory.stack.len() as u64;
+        self.stack_pointer = memory.stack_depth() as u64;
+    }
+
+    /// The VM address of the stack pointer compiled code left behind.
+    pub fn stack_address(&self) -> usize {
+        STACK_START + self.stack_pointer as usize
+    }
+}
+
+struct JitFunction {
+    code: ExecutableMemory,
+}
+
+impl JitFunction {
+    fn entry(&self) -> NativeFunction {
+        unsafe { std::mem::transmute::<*const u8, NativeFunction>(self.code.as_ptr()) }
+    }
+}
+
+/// Counts calls per function and compiles the ones that cross the threshold.
+pub struct Jit {
+    threshold: u32,
+    call_counts: HashMap<u32, u32>,
+    // `None` marks functions that could not be compiled so they are not retried.
+    functions: HashMap<u32, Option<JitFunction>>,
+}
+
+impl Jit {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            call_counts: HashMap::new(),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Records a call to `entry` and returns its native code once it is hot.
+    pub fn lookup(&mut self, entry: u32, byte_code_parser: &ByteCodeParser) -> Option<NativeFunction> {
+        if let Some(function) = self.functions.get(&entry) {
+            return function.as_ref().map(JitFunction::entry);
+        }
+        let count = self.call_counts.entry(entry).or_insert(0);
+        *count += 1;
+        if *count < self.threshold {
+            return None;
+        }
+        let function = compiler::compile(byte_code_parser, entry)
+            .and_then(|code| ExecutableMemory::new(&code))
+            .map(|code| JitFunction { code })
+            .ok();
+        let entry_point = function.as_ref().map(JitFunction::entry);
+        self.functions.insert(entry, function);
+        entry_point
+    }
+
+    pub fn compiled_functions(&self) -> usize {
+        self.functions.values().filter(|function| function.is_some()).count()
+    }
+}
+
+unsafe fn evaluator<'c, 'a>(context: *mut JitContext<'a>) -> (&'c mut JitContext<'a>, &'c mut Evaluator<'a>) {
+    let context = &mut *context;
+    let evaluator = &mut *context.evaluator;
+    (context, evaluator)
+}
+
+/// Executes a single instruction in the interpreter on behalf of compiled code.
+extern "C" fn execute_instruction(context: *mut JitContext<'_>, instruction_counter: u32) -> u32 {
+    let (context, evaluator) = unsafe { evaluator(context) };
+    evaluator.jit_execute_instruction(context, instruction_counter)
+}
+
+/// Performs a `CALL` from compiled code and runs the callee until it returns.
+extern "C" fn call_function(context: *mut JitContext<'_>, instruction_counter: u32, destination: u32) -> u32 {
+    let (context, evaluator) = unsafe { evaluator(context) };
+    evaluator.jit_call(context, instruction_counter, destination)
+}
@@ -0,0 +1,38 @@
+//! The proteus virtual machine as a library, for embedding it into other programs.
+//!
+//! ```
+//! use proteus_vm::evaluator::{Evaluator, Outcome};
+//! use proteus_vm::ffi::{FFIType, FFIValue, HostFunctions};
+//! use proteus_vm::loading::ByteCodeTranslator;
+//! use proteus_vm::preprocessor;
+//!
+//! let (code, symbol_table) = preprocessor::process("ffcall answer\nhalt\n");
+//! let byte_code = ByteCodeTranslator::new(&code, &symbol_table).translate();
+//!
+//! let mut host_functions = HostFunctions::standard();
+//! host_functions.register("answer", vec![], FFIType::I32, |_, _| Ok(FFIValue::I32(42)));
+//! let mut evaluator = Evaluator::with_host_functions(&byte_code, host_functions).unwrap();
+//! assert_eq!(evaluator.evaluate().unwrap(), Outcome::Halted);
+//! assert_eq!(evaluator.remove_top().unwrap(), 42);
+//! ```
+
+// Contains needed traits
+extern crate enum_index;
+// Contains derives
+#[macro_use]
+extern crate enum_index_derive;
+extern crate strum;
+#[macro_use]
+extern crate strum_macros;
+
+pub mod config;
+pub mod instructions;
+pub mod loading;
+pub mod evaluator;
+pub mod preprocessor;
+pub mod utils;
+pub mod memory;
+pub mod ffi;
+pub mod ir;
+#[cfg(feature = "jit")]
+pub mod jit;
@@ -1,12 +1,13 @@
 use std::str::FromStr;
 
-use enum_index::IndexEnum;
-
-use crate::ffi::FFIFunction;
 use crate::instructions::instruction::Instruction;
 use crate::instructions::OpCode;
 use crate::preprocessor::symbol_table::SymbolTable;
-use crate::utils::{decode_signed, decode_string, decode_unsigned, encode_signed, encode_string, encode_unsigned};
+use crate::utils::{decode_signed, decode_unsigned, encode_signed, encode_unsigned};
+
+/// Marks byte code that starts with an import table: the magic, the number of imports and every
+/// imported name as a length prefixed UTF-8 string. `ffcall` operands index into this table.
+const IMPORT_TABLE_MAGIC: [u8; 4] = *b"PSIT";
 
 pub struct ByteCodeTranslator<'a> {
     content: String,
@@ -21,6 +22,7 @@ impl<'a> ByteCodeTranslator<'a> {
         }
     }
     pub fn translate(&self) -> Vec<u8> {
+        let mut imports: Vec<&str> = Vec::new();
         let mut byte_code: Vec<u8> = Vec::new();
         let mut split = self.content.split_whitespace();
         while let Some(s) = split.next() {
@@ -29,10 +31,17 @@ impl<'a> ByteCodeTranslator<'a> {
             let operand = match self.parse_number(operand) {
                 None => {
                     if op_code == OpCode::FFCALL as u32 {
-                        let value = FFIFunction::get_index(operand).expect(format!("Unknown FFI function: {}", operand).as_str());
-                        value as i32
+                        // Native functions are imported by their whole declaration.
+                        let import = self.symbol_table.get_native(operand).unwrap_or(operand);
+                        match imports.iter().position(|existing| *existing == import) {
+                            Some(index) => index as i32,
+                            None => {
+                                imports.push(import);
+                                imports.len() as i32 - 1
+                            }
+                        }
                     } else {
-                        let symbol = self.symbol_table.get_symbol(operand).expect(format!("Unknown symbol: {}", operand).as_str());
+                        let symbol = self.symbol_table.get_symbol(operand).unwrap_or_else(|| panic!("Unknown symbol: {}", operand));
                         *symbol as i32
                     }
                 }
@@ -49,14 +58,21 @@ impl<'a> ByteCodeTranslator<'a> {
 
             byte_code.extend_from_slice(&encode_unsigned(offset));
         }
-        byte_code
+        let mut output = IMPORT_TABLE_MAGIC.to_vec();
+        output.extend_from_slice(&encode_unsigned(imports.len() as u32));
+        for import in imports {
+            output.extend_from_slice(&encode_unsigned(import.len() as u32));
+            output.extend_from_slice(import.as_bytes());
+        }
+        output.extend_from_slice(&byte_code);
+        output
     }
 
     fn parse_number(&self, s: &str) -> Option<i32> {
-        if s.starts_with("0b") {
-            i32::from_str_radix(&s[2..], 2).ok()
-        } else if s.starts_with("0x") {
-            i32::from_str_radix(&s[2..], 16).ok()
+        if let Some(binary) = s.strip_prefix("0b") {
+            i32::from_str_radix(binary, 2).ok()
+        } else if let Some(hex) = s.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()
         } else {
             i32::from_str(s).ok()
         }
@@ -65,15 +81,44 @@ impl<'a> ByteCodeTranslator<'a> {
 
 pub struct ByteCodeParser<'a> {
     byte_code: &'a [u8],
+    imports: Option<Vec<String>>,
     pub instruction_counter: usize,
 }
 
 impl<'a> ByteCodeParser<'a> {
-    pub fn new(byte_code: &'a [u8]) -> Self {
-        Self {
+    pub fn new(byte_code: &'a [u8]) -> Result<Self, String> {
+        let (imports, byte_code) = match byte_code.strip_prefix(&IMPORT_TABLE_MAGIC) {
+            Some(rest) => {
+                let (imports, rest) = Self::parse_imports(rest)?;
+                (Some(imports), rest)
+            }
+            None => (None, byte_code),
+        };
+        Ok(Self {
             byte_code,
+            imports,
             instruction_counter: 0,
+        })
+    }
+
+    fn parse_imports(mut byte_code: &[u8]) -> Result<(Vec<String>, &[u8]), String> {
+        let count = decode_unsigned(0, byte_code).map_err(|_| "Truncated import table")?;
+        byte_code = &byte_code[4..];
+        let mut imports = Vec::new();
+        for _ in 0..count {
+            let length = decode_unsigned(0, byte_code).map_err(|_| "Truncated import table")? as usize;
+            let name = byte_code.get(4..4 + length).ok_or("Truncated import table")?;
+            let name = String::from_utf8(name.to_vec()).map_err(|_| "Import name is not valid UTF-8")?;
+            imports.push(name);
+            byte_code = &byte_code[4 + length..];
         }
+        Ok((imports, byte_code))
+    }
+
+    /// The names `ffcall` operands refer to, or `None` for byte code without an import table, in
+    /// which case the operands index the host functions directly.
+    pub fn imports(&self) -> Option<&[String]> {
+        self.imports.as_deref()
     }
 
     const fn get_instruction_size() -> usize {
@@ -81,18 +126,27 @@ impl<'a> ByteCodeParser<'a> {
     }
 
     pub fn parse_instruction(&mut self) -> Result<Option<Instruction>, String> {
-        let index = self.instruction_counter * Self::get_instruction_size();
+        let instruction = self.instruction_at(self.instruction_counter)?;
+        self.go_to(self.instruction_counter + 1);
+        Ok(Some(instruction))
+    }
+
+    /// Decodes the instruction at the given index without moving the instruction counter.
+    pub fn instruction_at(&self, instruction_count: usize) -> Result<Instruction, String> {
+        let index = instruction_count * Self::get_instruction_size();
         let op_code = decode_unsigned(index, self.byte_code).map_err(|_| "Reached end of programm while parsing. This means that there is either no halt or no return in a function.")?;
         let op_code = unsafe { OpCode::from_op_code(op_code) };
         let operand = decode_signed(index + 4, self.byte_code).unwrap_or(0);
         let offset = decode_unsigned(index + 8, self.byte_code).unwrap_or(4);
-
-        self.go_to(self.instruction_counter + 1);
-        Ok(Some(Instruction {
+        Ok(Instruction {
             opcode: op_code,
             operand,
             offset,
-        }))
+        })
+    }
+
+    pub fn instruction_count(&self) -> usize {
+        self.byte_code.len() / Self::get_instruction_size()
     }
 
 
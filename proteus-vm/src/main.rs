@@ -1,44 +1,204 @@
-// Contains needed traits
-extern crate enum_index;
-// Contains derives
-#[macro_use]
-extern crate enum_index_derive;
-extern crate strum;
-#[macro_use]
-extern crate strum_macros;
-
-
+use std::error::Error;
 use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
 
-use clap::Arg;
+use clap::{Arg, ArgAction, ArgMatches};
 
-use crate::evaluator::Evaluator;
-
-mod instructions;
-mod loading;
-mod evaluator;
-mod preprocessor;
-mod utils;
-mod memory;
-mod ffi;
+#[cfg(feature = "jit")]
+use proteus_vm::jit;
+use proteus_vm::evaluator::{Checkpoint, Evaluator, Outcome};
+use proteus_vm::ffi::{HostFunctions, StandardOptions};
+#[cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+use proteus_vm::ffi::native::NativeImport;
+use proteus_vm::config::{self, VmConfig};
+use proteus_vm::memory::snapshot::{HeapSnapshot, SnapshotDiff, SnapshotFile};
+use proteus_vm::{ir, loading, preprocessor};
 
 fn main() {
+    #[allow(unused_mut)]
+    let mut run_args = vec![
+        Arg::new("file")
+            .help("The file to run")
+            .required(true)
+            .index(1),
+        Arg::new("step")
+            .help("Step through the execution")
+            .num_args(0)
+            .required(false)
+            .short('s'),
+        Arg::new("registers")
+            .help("Execute the register based IR instead of the stack byte code")
+            .num_args(0)
+            .required(false)
+            .long("registers"),
+        Arg::new("max-instructions")
+            .help("Stop after executing this many instructions")
+            .required(false)
+            .long("max-instructions")
+            .value_parser(clap::value_parser!(u64)),
+        Arg::new("timeout")
+            .help("Stop after running for this many milliseconds")
+            .required(false)
+            .long("timeout")
+            .value_parser(clap::value_parser!(u64)),
+        Arg::new("allow-read")
+            .help("Allow the program to read files in this directory")
+            .required(false)
+            .long("allow-read")
+            .action(ArgAction::Append)
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("allow-write")
+            .help("Allow the program to write files in this directory")
+            .required(false)
+            .long("allow-write")
+            .action(ArgAction::Append)
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("allow-env")
+            .help("Allow the program to read environment variables")
+            .num_args(0)
+            .required(false)
+            .long("allow-env"),
+        Arg::new("allow-native")
+            .help("Allow the program to load the native functions it declares with .native")
+            .num_args(0)
+            .required(false)
+            .long("allow-native"),
+        Arg::new("args")
+            .help("Arguments passed to the program, after --")
+            .num_args(0..)
+            .index(2)
+            .last(true),
+        Arg::new("stack-size")
+            .help("Size of the stack in bytes, like 64K")
+            .required(false)
+            .long("stack-size")
+            .value_parser(config::parse_size),
+        Arg::new("data-size")
+            .help("Size of the data segment for globals in bytes, like 64K")
+            .required(false)
+            .long("data-size")
+            .value_parser(config::parse_size),
+        Arg::new("max-call-depth")
+            .help("Fail calls nested deeper than this with a stack overflow")
+            .required(false)
+            .long("max-call-depth")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("heap-size")
+            .help("Initial size of the heap in bytes, like 1M")
+            .required(false)
+            .long("heap-size")
+            .value_parser(config::parse_size),
+        Arg::new("max-heap-size")
+            .help("Let the heap grow up to this many bytes")
+            .required(false)
+            .long("max-heap-size")
+            .value_parser(config::parse_size),
+        Arg::new("memcheck")
+            .help("Check heap accesses for use after free, uninitialized reads and overruns, and report leaks")
+            .num_args(0)
+            .required(false)
+            .long("memcheck"),
+        Arg::new("stack-tags")
+            .help("Check that every value is popped from the stack with the width it was pushed with")
+            .num_args(0)
+            .required(false)
+            .long("stack-tags"),
+        Arg::new("mem-stats")
+            .help("Print heap and stack usage and the live heap allocations when the program stops")
+            .num_args(0)
+            .required(false)
+            .long("mem-stats"),
+        Arg::new("heap-snapshot")
+            .help("Write the heap snapshots taken by heap_snapshot and one taken at exit to this JSON file")
+            .required(false)
+            .long("heap-snapshot")
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("checkpoint")
+            .help("Write a checkpoint to this file if the instruction limit or the timeout stops the program")
+            .required(false)
+            .long("checkpoint")
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("restore")
+            .help("Continue the program from a checkpoint written by --checkpoint")
+            .required(false)
+            .long("restore")
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("refcount-debug")
+            .help("Report objects whose reference count did not drop to zero when the program halts")
+            .num_args(0)
+            .required(false)
+            .long("refcount-debug"),
+        Arg::new("gc")
+            .help("Free unreachable heap allocations with a garbage collector")
+            .num_args(0)
+            .required(false)
+            .long("gc"),
+        Arg::new("gc-threshold")
+            .help("Collect garbage after allocating this many bytes, like 256K. Implies --gc")
+            .required(false)
+            .long("gc-threshold")
+            .value_parser(config::parse_size),
+        Arg::new("seed")
+            .help("Seed for the random number generator, for reproducible runs")
+            .required(false)
+            .long("seed")
+            .value_parser(clap::value_parser!(u64)),
+    ];
+    #[cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+    run_args.push(
+        Arg::new("native")
+            .help("Make a native function available, as name=library:symbol(i32,string)->i32")
+            .required(false)
+            .long("native")
+            .action(ArgAction::Append)
+            .value_parser(clap::value_parser!(NativeImport)),
+    );
+    #[cfg(feature = "jit")]
+    run_args.extend([
+        Arg::new("jit")
+            .help("Compile hot functions to native code, unless --max-instructions, --timeout or --stack-tags is given")
+            .num_args(0)
+            .required(false)
+            .long("jit"),
+        Arg::new("jit-threshold")
+            .help("Number of calls after which a function is compiled")
+            .required(false)
+            .long("jit-threshold")
+            .value_parser(clap::value_parser!(u32)),
+    ]);
     let matches = clap::Command::new("Rust VM")
         .version("0.1.0")
         .author("Julian Hartl <")
         .about("A simple virtual machine written in Rust")
         .subcommand(clap::Command::new("run")
             .about("Runs a file")
+            .args(run_args))
+        .subcommand(clap::Command::new("bench")
+            .about("Compares the stack interpreter with the register IR interpreter")
             .args(vec![
                 Arg::new("file")
                     .help("The file to run")
                     .required(true)
                     .index(1),
-                Arg::new("step")
-                    .help("Step through the execution")
-                    .num_args(0)
+                Arg::new("iterations")
+                    .help("How often the file is run by each interpreter")
                     .required(false)
-                    .short('s'),
+                    .short('n')
+                    .value_parser(clap::value_parser!(u32)),
+            ]))
+        .subcommand(clap::Command::new("diff-snapshots")
+            .about("Shows which allocation sites grew between two heap snapshots")
+            .args(vec![
+                Arg::new("before")
+                    .help("A file written by --heap-snapshot, optionally followed by :label to pick a snapshot other than the last")
+                    .required(true)
+                    .index(1),
+                Arg::new("after")
+                    .help("The file with the later snapshot, in the same form")
+                    .required(true)
+                    .index(2),
             ]))
         .subcommand(clap::Command::new("transpile")
             .about("Transpiles a file into proteus byte code")
@@ -58,7 +218,72 @@ fn main() {
 
         println!("Running file: {}", file);
         let content = fs::read(file).unwrap();
-        let mut evaluator = Evaluator::new(&content);
+        let options = StandardOptions {
+            seed: matches.get_one::<u64>("seed").copied(),
+            allow_read: matches.get_many::<PathBuf>("allow-read").unwrap_or_default().cloned().collect(),
+            allow_write: matches.get_many::<PathBuf>("allow-write").unwrap_or_default().cloned().collect(),
+            args: matches.get_many::<String>("args").unwrap_or_default().cloned().collect(),
+            allow_env: matches.get_flag("allow-env"),
+            allow_native: matches.get_flag("allow-native"),
+        };
+        #[allow(unused_mut)]
+        let mut host_functions = HostFunctions::with_options(&options);
+        #[cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+        for import in matches.get_many::<NativeImport>("native").unwrap_or_default() {
+            if let Err(e) = host_functions.register_native(import) {
+                eprintln!("Could not load native function {}: {}", import.name, e);
+                process::exit(1);
+            }
+        }
+        let mut config = VmConfig::default();
+        if let Some(stack_size) = matches.get_one::<usize>("stack-size") {
+            config.stack_size = *stack_size;
+        }
+        config.max_call_depth = matches.get_one::<usize>("max-call-depth").copied();
+        if let Some(data_size) = matches.get_one::<usize>("data-size") {
+            config.data_size = *data_size;
+        }
+        if let Some(heap_size) = matches.get_one::<usize>("heap-size") {
+            config.heap_size = *heap_size;
+            config.max_heap_size = *heap_size;
+        }
+        if let Some(max_heap_size) = matches.get_one::<usize>("max-heap-size") {
+            config = config.growable_heap(*max_heap_size);
+        }
+        config.memcheck = matches.get_flag("memcheck");
+        config.stack_tags = matches.get_flag("stack-tags");
+        if let Some(threshold) = matches.get_one::<usize>("gc-threshold") {
+            config.gc_threshold = Some(*threshold);
+        } else if matches.get_flag("gc") {
+            config.gc_threshold = Some(config::DEFAULT_GC_THRESHOLD);
+        }
+        if let Err(e) = config.validate() {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+        let mut evaluator = Evaluator::with_config(&content, host_functions, &config).unwrap_or_else(|e| {
+            eprintln!("Could not load {}: {}", file, e);
+            process::exit(1);
+        });
+        if let Some(path) = matches.get_one::<PathBuf>("restore") {
+            let restored = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|json| Checkpoint::from_json(&json))
+                .and_then(|checkpoint| evaluator.restore(checkpoint));
+            if let Err(e) = restored {
+                eprintln!("Could not restore {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "jit")]
+        if matches.get_flag("jit") {
+            let threshold = matches.get_one::<u32>("jit-threshold").copied().unwrap_or(jit::DEFAULT_THRESHOLD);
+            evaluator.enable_jit(threshold);
+        }
+        evaluator.set_fuel(matches.get_one::<u64>("max-instructions").copied());
+        if let Some(timeout) = matches.get_one::<u64>("timeout") {
+            evaluator.set_timeout(Duration::from_millis(*timeout));
+        }
         let step = matches.get_flag("step");
         if step {
             let mut next_breakpoint = Some(1);
@@ -89,6 +314,13 @@ fn main() {
                             true
                         }
 
+                        command if command.starts_with("#s") => {
+                            let label = command[2..].trim();
+                            evaluator.memory.take_snapshot(label);
+                            println!("Took heap snapshot {:?}", label);
+                            true
+                        }
+
                         command if command.starts_with("#b") => {
                             let split: Vec<&str> = command.split(" ").map(|s| s.trim()).collect();
                             let instruction = split[1].parse::<usize>().unwrap();
@@ -102,15 +334,62 @@ fn main() {
                     }
                 }
             }
+            process::exit(evaluator.exit_code());
+        } else if matches.get_flag("registers") {
+            let program = ir::translate(&evaluator.byte_code_parser).unwrap();
+            let now = std::time::Instant::now();
+            println!();
+            let outcome = evaluator.evaluate_registers(&program).unwrap_or_else(|e| fail(e));
+            println!();
+            report_heap(&evaluator, &config, outcome, matches);
+            save_checkpoint(&evaluator, outcome, matches);
+
+            println!("Execution time: {}ms", now.elapsed().as_millis());
+            report_outcome(outcome);
+            process::exit(evaluator.exit_code());
         } else {
             let now = std::time::Instant::now();
             println!();
-            evaluator.evaluate().unwrap();
+            let outcome = evaluator.evaluate().unwrap_or_else(|e| fail(e));
             println!();
+            report_heap(&evaluator, &config, outcome, matches);
+            save_checkpoint(&evaluator, outcome, matches);
 
             println!("Execution time: {}ms", now.elapsed().as_millis());
+            report_outcome(outcome);
+            process::exit(evaluator.exit_code());
+        }
+    }
 
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        let file = matches.get_one::<String>("file").unwrap();
+        let iterations = matches.get_one::<u32>("iterations").copied().unwrap_or(10);
+        let content = fs::read(file).unwrap();
+
+        let now = std::time::Instant::now();
+        for _ in 0..iterations {
+            Evaluator::new(&content).unwrap().evaluate().unwrap();
         }
+        let stack_time = now.elapsed() / iterations;
+
+        let now = std::time::Instant::now();
+        for _ in 0..iterations {
+            let mut evaluator = Evaluator::new(&content).unwrap();
+            let program = ir::translate(&evaluator.byte_code_parser).unwrap();
+            evaluator.evaluate_registers(&program).unwrap();
+        }
+        let register_time = now.elapsed() / iterations;
+
+        println!();
+        println!("Stack interpreter:    {:.3}ms", stack_time.as_secs_f64() * 1000.0);
+        println!("Register interpreter: {:.3}ms (including translation)", register_time.as_secs_f64() * 1000.0);
+        println!("Speedup: {:.2}x", stack_time.as_secs_f64() / register_time.as_secs_f64());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("diff-snapshots") {
+        let before = load_snapshot(matches.get_one::<String>("before").unwrap());
+        let after = load_snapshot(matches.get_one::<String>("after").unwrap());
+        println!("{}", SnapshotDiff::new(&before, &after));
     }
 
     if let Some(matches) = matches.subcommand_matches("transpile") {
@@ -128,7 +407,79 @@ fn main() {
     }
 }
 
+/// Writes a checkpoint for `--checkpoint` if the program was stopped before it halted.
+fn save_checkpoint(evaluator: &Evaluator, outcome: Outcome, matches: &ArgMatches) {
+    let Some(path) = matches.get_one::<PathBuf>("checkpoint") else {
+        return;
+    };
+    if outcome == Outcome::Halted {
+        return;
+    }
+    if let Err(e) = fs::write(path, evaluator.checkpoint().to_json()) {
+        eprintln!("Could not write checkpoint to {}: {}", path.display(), e);
+        process::exit(1);
+    }
+    eprintln!("Wrote checkpoint to {}", path.display());
+}
 
+fn report_heap(evaluator: &Evaluator, config: &VmConfig, outcome: Outcome, matches: &ArgMatches) {
+    if let Some(stats) = evaluator.memory.gc_stats() {
+        eprintln!(
+            "GC: {} collections reclaimed {} objects with {} bytes",
+            stats.collections, stats.objects_reclaimed, stats.bytes_reclaimed
+        );
+    }
+    if config.memcheck && outcome == Outcome::Halted {
+        eprintln!("{}", evaluator.leak_report());
+    }
+    if matches.get_flag("refcount-debug") && outcome == Outcome::Halted {
+        eprintln!("{}", evaluator.refcount_report());
+    }
+    if matches.get_flag("mem-stats") {
+        eprintln!("{}", evaluator.memory_report());
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("heap-snapshot") {
+        let mut snapshots = SnapshotFile { snapshots: evaluator.memory.snapshots.clone() };
+        snapshots.snapshots.push(evaluator.memory.snapshot("exit"));
+        if let Err(e) = fs::write(path, snapshots.to_json()) {
+            eprintln!("Could not write heap snapshots to {}: {}", path.display(), e);
+        }
+    }
+}
 
+/// Reads the snapshot named by `file` or `file:label`.
+fn load_snapshot(argument: &str) -> HeapSnapshot {
+    let (path, label) = match argument.rsplit_once(':') {
+        Some((path, label)) if !fs::exists(argument).unwrap_or(false) => (path, Some(label)),
+        _ => (argument, None),
+    };
+    let snapshots = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| SnapshotFile::from_json(&json))
+        .unwrap_or_else(|e| {
+            eprintln!("Could not read heap snapshots from {}: {}", path, e);
+            process::exit(1);
+        });
+    match snapshots.find(label) {
+        Some(snapshot) => snapshot.clone(),
+        None => {
+            eprintln!("{} has no heap snapshot {}", path, label.unwrap_or(""));
+            process::exit(1);
+        }
+    }
+}
 
+fn fail(error: Box<dyn Error>) -> ! {
+    eprintln!("{}", error);
+    process::exit(1);
+}
 
+fn report_outcome(outcome: Outcome) {
+    let message = match outcome {
+        Outcome::Halted => return,
+        Outcome::OutOfFuel => "Execution stopped: instruction limit reached",
+        Outcome::TimedOut => "Execution stopped: timeout reached",
+    };
+    eprintln!("{}", message);
+    process::exit(2);
+}
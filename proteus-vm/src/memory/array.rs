@@ -0,0 +1,150 @@
+//! Growable arrays on the heap, for host functions and compilers to build lists and string
+//! builders on.
+//!
+//! An array is a 16 byte allocation holding its length, its capacity, the size of its elements and
+//! the address of a second allocation with room for `capacity` elements. Pushing past the capacity
+//! doubles it with `reallocate_heap`, which moves the elements only if they cannot grow in place.
+//! The address of the array itself never changes.
+//!
+//! The collector cannot see the address of an allocation while it is only held here, so both are
+//! made with collection suspended; callers keep the array itself on the stack or in the heap.
+
+use crate::utils::{decode_unsigned, encode_unsigned};
+
+use super::Memory;
+
+const HEADER_SIZE: usize = 16;
+
+struct ArrayHeader {
+    length: usize,
+    capacity: usize,
+    element_size: usize,
+    data: usize,
+}
+
+impl Memory {
+    /// Allocates an empty array of elements of 1, 2 or 4 bytes with room for `capacity` of them.
+    pub fn array_new(&mut self, element_size: usize, capacity: usize) -> Result<usize, String> {
+        if ![1, 2, 4].contains(&element_size) {
+            return Err(format!("Arrays cannot hold elements of {} bytes", element_size));
+        }
+        self.without_gc(|memory| {
+            let data = memory.allocate_heap(capacity * element_size)?;
+            let array = memory.allocate_heap(HEADER_SIZE)?;
+            memory.write_array_header(array, &ArrayHeader { length: 0, capacity, element_size, data })?;
+            Ok(array)
+        })
+    }
+
+    pub fn array_len(&self, array: usize) -> Result<usize, String> {
+        Ok(self.array_header(array)?.length)
+    }
+
+    pub fn array_capacity(&self, array: usize) -> Result<usize, String> {
+        Ok(self.array_header(array)?.capacity)
+    }
+
+    pub fn array_element_size(&self, array: usize) -> Result<usize, String> {
+        Ok(self.array_header(array)?.element_size)
+    }
+
+    /// The address of the first element. It changes when the array grows.
+    pub fn array_data(&self, array: usize) -> Result<usize, String> {
+        Ok(self.array_header(array)?.data)
+    }
+
+    /// The bytes of the element at `index`.
+    pub fn array_get(&self, array: usize, index: usize) -> Result<&[u8], String> {
+        let header = self.array_header(array)?;
+        check_index(&header, index)?;
+        self.load(header.data + index * header.element_size, header.element_size)
+    }
+
+    pub fn array_set(&mut self, array: usize, index: usize, element: &[u8]) -> Result<(), String> {
+        let header = self.array_header(array)?;
+        check_index(&header, index)?;
+        check_elements(&header, element)?;
+        self.store(header.data + index * header.element_size, element)
+    }
+
+    /// Appends the elements in `elements`, growing the array if needed.
+    pub fn array_push(&mut self, array: usize, elements: &[u8]) -> Result<(), String> {
+        let header = self.array_header(array)?;
+        check_elements(&header, elements)?;
+        let length = header.length + elements.len() / header.element_size;
+        self.array_reserve(array, length)?;
+        let mut header = self.array_header(array)?;
+        self.store(header.data + header.length * header.element_size, elements)?;
+        header.length = length;
+        self.write_array_header(array, &header)
+    }
+
+    /// Removes the last element and returns its bytes.
+    pub fn array_pop(&mut self, array: usize) -> Result<Vec<u8>, String> {
+        let mut header = self.array_header(array)?;
+        if header.length == 0 {
+            return Err(format!("Array at {} is empty", array));
+        }
+        header.length -= 1;
+        let element = self.load(header.data + header.length * header.element_size, header.element_size)?.to_vec();
+        self.write_array_header(array, &header)?;
+        Ok(element)
+    }
+
+    /// Makes room for at least `capacity` elements, at least doubling the capacity if it grows.
+    pub fn array_reserve(&mut self, array: usize, capacity: usize) -> Result<(), String> {
+        let mut header = self.array_header(array)?;
+        if capacity <= header.capacity {
+            return Ok(());
+        }
+        header.capacity = capacity.max(header.capacity * 2);
+        self.without_gc(|memory| {
+            header.data = memory.reallocate_heap(header.data, header.capacity * header.element_size)?;
+            memory.write_array_header(array, &header)
+        })
+    }
+
+    /// Frees the array and its elements.
+    pub fn array_free(&mut self, array: usize) -> Result<(), String> {
+        let header = self.array_header(array)?;
+        self.free_allocation(header.data)?;
+        self.free_allocation(array)
+    }
+
+    fn array_header(&self, array: usize) -> Result<ArrayHeader, String> {
+        let bytes = self.load(array, HEADER_SIZE)?;
+        let field = |index: usize| decode_unsigned(index * 4, bytes).map(|value| value as usize);
+        let header = ArrayHeader {
+            length: field(0)?,
+            capacity: field(1)?,
+            element_size: field(2)?,
+            data: field(3)?,
+        };
+        if ![1, 2, 4].contains(&header.element_size) || header.length > header.capacity {
+            return Err(format!("{} is not an array", array));
+        }
+        Ok(header)
+    }
+
+    fn write_array_header(&mut self, array: usize, header: &ArrayHeader) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        for field in [header.length, header.capacity, header.element_size, header.data] {
+            bytes.extend_from_slice(&encode_unsigned(field as u32));
+        }
+        self.store(array, &bytes)
+    }
+}
+
+fn check_index(header: &ArrayHeader, index: usize) -> Result<(), String> {
+    if index >= header.length {
+        return Err(format!("Index {} is out of bounds for an array of length {}", index, header.length));
+    }
+    Ok(())
+}
+
+fn check_elements(header: &ArrayHeader, elements: &[u8]) -> Result<(), String> {
+    if !elements.len().is_multiple_of(header.element_size) {
+        return Err(format!("{} bytes are not a whole number of {} byte elements", elements.len(), header.element_size));
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Optional mark-and-sweep garbage collection of the heap.
+//!
+//! The roots are found by scanning the whole stack conservatively: every four bytes that point into
+//! a heap allocation keep it alive. A collection runs when the bytes allocated since the last one
+//! exceed the threshold, when an allocation does not fit, and on the `gc` instruction.
+//!
+//! No collection runs while a host function is called: host functions keep heap addresses in Rust
+//! variables the collector cannot see, so an allocation could sweep the one made just before it.
+
+use serde::{Deserialize, Serialize};
+
+use super::Memory;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcStats {
+    pub collections: usize,
+    pub objects_reclaimed: usize,
+    pub bytes_reclaimed: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collector {
+    /// The number of bytes that may be allocated between collections.
+    threshold: usize,
+    allocated: usize,
+    /// Set while running code that holds heap addresses outside the VM's memory.
+    #[serde(skip)]
+    suspended: bool,
+    pub stats: GcStats,
+}
+
+impl Collector {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            allocated: 0,
+            suspended: false,
+            stats: GcStats::default(),
+        }
+    }
+}
+
+impl Memory {
+    /// Frees every heap allocation that is not reachable from the stack. Does nothing unless the
+    /// collector is enabled and not suspended.
+    pub fn collect_garbage(&mut self) {
+        if !self.gc_active() {
+            return;
+        }
+        let roots = self.roots();
+        let freed = self.heap.collect(roots, self.heap_start());
+        if let Some(memcheck) = &mut self.memcheck {
+            for (start, allocation) in &freed {
+                memcheck.freed(*start, allocation.size);
+            }
+        }
+        let gc = self.gc.as_mut().unwrap();
+        gc.allocated = 0;
+        gc.stats.collections += 1;
+        gc.stats.objects_reclaimed += freed.len();
+        gc.stats.bytes_reclaimed += freed.iter().map(|(_, allocation)| allocation.size).sum::<usize>();
+    }
+
+    pub fn gc_stats(&self) -> Option<GcStats> {
+        self.gc.as_ref().map(|gc| gc.stats)
+    }
+
+    /// Runs `f` without collecting garbage, for code that keeps heap addresses where the collector
+    /// cannot find them. Allocations that do not fit fail instead of collecting.
+    pub fn without_gc<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let suspended = self.gc.as_mut().map(|gc| std::mem::replace(&mut gc.suspended, true));
+        let result = f(self);
+        if let (Some(gc), Some(suspended)) = (&mut self.gc, suspended) {
+            gc.suspended = suspended;
+        }
+        result
+    }
+
+    pub(super) fn gc_active(&self) -> bool {
+        self.gc.as_ref().is_some_and(|gc| !gc.suspended)
+    }
+
+    /// Whether allocating `size` more bytes should be preceded by a collection.
+    pub(super) fn gc_due(&self, size: usize) -> bool {
+        self.gc.as_ref().is_some_and(|gc| !gc.suspended && gc.allocated + size > gc.threshold)
+    }
+
+    pub(super) fn gc_allocated(&mut self, size: usize) {
+        if let Some(gc) = &mut self.gc {
+            gc.allocated += size;
+        }
+    }
+
+    /// The heap offsets of every four bytes on the stack and in the data segment, at any alignment,
+    /// that could be a pointer into the heap.
+    fn roots(&self) -> Vec<usize> {
+        let heap_start = self.heap_start();
+        self.stack[..self.stack_depth()]
+            .windows(4)
+            .chain(self.data.windows(4))
+            .filter_map(|word| (u32::from_be_bytes(word.try_into().unwrap()) as usize).checked_sub(heap_start))
+            .collect()
+    }
+}
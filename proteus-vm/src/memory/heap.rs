@@ -1,70 +1,397 @@
-#[derive(Debug)]
-pub struct Heap {
-    pub memory: Vec<u8>,
-    pub free_list: Vec<FreeBlock>,
+//! A heap with segregated free lists and boundary tags.
+//!
+//! The memory is split into blocks that start with a 4 byte header and end with a 4 byte footer,
+//! both holding the size of the block and whether it is allocated. Looking at the footer before
+//! and the header after a block finds its neighbours in constant time, so freed blocks are merged
+//! with free neighbours right away. Free blocks are kept in doubly linked lists by size class, with
+//! the links stored in the otherwise unused payload, and allocation takes the first block that fits
+//! from the smallest class that can hold the request.
+//!
+//! The header of an allocated block is followed by the reference count of the allocation, which
+//! starts at one and is changed by `retain` and `release`. Addresses handed out point at the
+//! payload, right after the reference count.
+//!
+//! The low bits of a header are flags: whether the block is allocated, whether the garbage
+//! collector found it reachable, and whether it holds no pointers so the collector need not scan
+//! it. Footers only carry the allocated flag.
+//!
+//! Every live allocation is tracked along with the instruction that made it, so that freeing
+//! memory that is not a live allocation, or freeing it with the wrong size, is reported instead of
+//! corrupting the free lists.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::layout::MAX_HEAP_SIZE;
+
+const TAG_SIZE: usize = 4;
+/// The tag and the reference count of an allocated block.
+const HEADER_SIZE: usize = 8;
+const ALIGNMENT: usize = 8;
+/// A header, the two free list links and a footer.
+const MIN_BLOCK_SIZE: usize = 16;
+const SIZE_CLASSES: usize = 28;
+const ALLOCATED: u32 = 1;
+const MARKED: u32 = 2;
+const NO_POINTERS: u32 = 4;
+const FLAGS: u32 = ALLOCATED | MARKED | NO_POINTERS;
+/// Marks the end of a free list.
+const NONE: u32 = u32::MAX;
+
+/// A live allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Allocation {
+    /// The size that was requested.
+    pub size: usize,
+    /// The instruction that made the allocation, `None` if the host made it.
+    pub site: Option<usize>,
 }
 
-#[derive(Debug)]
-pub struct FreeBlock {
-    pub start: usize,
+/// An allocation that was freed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FreedAllocation {
     pub size: usize,
+    pub site: Option<usize>,
+    /// The instruction that freed the allocation, `None` if the host freed it.
+    pub freed_by: Option<usize>,
+}
+
+/// Why memory could not be freed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeError {
+    /// The allocation at the address was already freed.
+    DoubleFree { site: Option<usize>, freed_by: Option<usize> },
+    /// The allocation was freed with a different size than it was made with.
+    SizeMismatch { size: usize, allocation: Allocation },
+    /// The address points into the allocation at `start` rather than at its start.
+    Interior { start: usize, allocation: Allocation },
+    /// The address was never handed out by the heap.
+    NotAllocated,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heap {
+    #[serde(with = "crate::utils::hex")]
+    pub memory: Vec<u8>,
+    /// The first free block of every size class.
+    free_lists: [u32; SIZE_CLASSES],
+    /// The size the memory may grow to.
+    limit: usize,
+    /// Every live allocation by its address.
+    allocations: BTreeMap<usize, Allocation>,
+    /// Every freed allocation by its address, until the address is handed out again.
+    freed: BTreeMap<usize, FreedAllocation>,
+    /// The instruction allocations and frees are attributed to.
+    site: Option<usize>,
 }
 
 impl Heap {
     pub fn new(size: usize) -> Self {
-        Self {
+        Self::growable(size, size)
+    }
+
+    /// A heap of `size` bytes that grows when an allocation does not fit, up to `limit` bytes.
+    pub fn growable(size: usize, limit: usize) -> Self {
+        // Memory too small for a block starts out empty, so that every byte belongs to a block.
+        let size = match align_down(size) {
+            size if size >= MIN_BLOCK_SIZE => size,
+            _ => 0,
+        };
+        let mut heap = Self {
             memory: vec![0; size],
-            free_list: vec![FreeBlock { start: 0, size }],
+            free_lists: [NONE; SIZE_CLASSES],
+            limit: align_down(limit).max(size),
+            allocations: BTreeMap::new(),
+            freed: BTreeMap::new(),
+            site: None,
+        };
+        if size > 0 {
+            heap.push_free(0, size);
         }
+        heap
     }
 
     pub fn allocate(&mut self, size: usize) -> Result<usize, String> {
-        let mut best_fit: Option<usize> = None;
-        for (index, block) in self.free_list.iter().enumerate() {
-            if block.size >= size {
-                match best_fit {
-                    Some(best_fit_index) => {
-                        if self.free_list[best_fit_index].size > block.size {
-                            best_fit = Some(index);
-                        }
-                    }
-                    None => best_fit = Some(index),
+        let block_size = block_size(size).ok_or_else(out_of_memory)?;
+        let start = match self.find_free(block_size) {
+            Some(start) => start,
+            None => self.grow(block_size)?,
+        };
+        let available = self.tag(start).0;
+        self.remove_free(start, available);
+        if available - block_size >= MIN_BLOCK_SIZE {
+            self.push_free(start + block_size, available - block_size);
+            self.set_tags(start, block_size, true);
+        } else {
+            self.set_tags(start, available, true);
+        }
+        let address = start + HEADER_SIZE;
+        self.set_word(address - TAG_SIZE, 1);
+        self.allocations.insert(address, Allocation { size, site: self.site });
+        self.freed.remove(&address);
+        Ok(address)
+    }
+
+    /// Sets the instruction that following allocations and frees are attributed to.
+    pub fn set_site(&mut self, site: Option<usize>) {
+        self.site = site;
+    }
+
+    /// Frees the allocation at `start`, which must have been made with `size` bytes.
+    pub fn free(&mut self, start: usize, size: usize) -> Result<(), FreeError> {
+        self.check_free(start, Some(size))?;
+        self.release_allocation(start);
+        Ok(())
+    }
+
+    /// Frees the allocation at `start` with the size it was allocated with.
+    pub fn free_allocation(&mut self, start: usize) -> Result<(), FreeError> {
+        self.check_free(start, None)?;
+        self.release_allocation(start);
+        Ok(())
+    }
+
+    /// Checks that `start` is a live allocation that may be freed, and that it was made with `size`
+    /// bytes if given.
+    pub fn check_free(&self, start: usize, size: Option<usize>) -> Result<(), FreeError> {
+        if let Some(allocation) = self.allocations.get(&start) {
+            return match size {
+                Some(size) if size != allocation.size => Err(FreeError::SizeMismatch { size, allocation: *allocation }),
+                _ => Ok(()),
+            };
+        }
+        if let Some((allocation_start, allocation)) = self.allocation_containing(start) {
+            return Err(FreeError::Interior { start: allocation_start, allocation: *allocation });
+        }
+        match self.freed.get(&start) {
+            Some(freed) => Err(FreeError::DoubleFree { site: freed.site, freed_by: freed.freed_by }),
+            None => Err(FreeError::NotAllocated),
+        }
+    }
+
+    /// The reference count of the allocation at `start`, if it is the start of a live allocation.
+    pub fn reference_count(&self, start: usize) -> Option<u32> {
+        self.allocations.contains_key(&start).then(|| self.word(start - TAG_SIZE))
+    }
+
+    /// Adds a reference to the allocation at `start` and returns the new count.
+    pub fn retain(&mut self, start: usize) -> Result<u32, FreeError> {
+        self.check_free(start, None)?;
+        let count = self.word(start - TAG_SIZE) + 1;
+        self.set_word(start - TAG_SIZE, count);
+        Ok(count)
+    }
+
+    /// Drops a reference to the allocation at `start` and returns the remaining count. The caller
+    /// frees the allocation once none remain.
+    pub fn release(&mut self, start: usize) -> Result<u32, FreeError> {
+        self.check_free(start, None)?;
+        let count = self.word(start - TAG_SIZE) - 1;
+        self.set_word(start - TAG_SIZE, count);
+        Ok(count)
+    }
+
+    /// The size `start` was allocated with, if it is the start of a live allocation.
+    pub fn allocation_size(&self, start: usize) -> Option<usize> {
+        self.allocations.get(&start).map(|allocation| allocation.size)
+    }
+
+    /// The live allocation that `address` points into, along with its start.
+    pub fn allocation_containing(&self, address: usize) -> Option<(usize, &Allocation)> {
+        let (&start, allocation) = self.allocations.range(..=address).next_back()?;
+        (address < start + allocation.size).then_some((start, allocation))
+    }
+
+    /// The freed allocation that `address` points into, along with its start.
+    pub fn freed_allocation_containing(&self, address: usize) -> Option<(usize, &FreedAllocation)> {
+        let (&start, freed) = self.freed.range(..=address).next_back()?;
+        (address < start + freed.size).then_some((start, freed))
+    }
+
+    /// Every live allocation with its start, in the order of their addresses.
+    pub fn allocations(&self) -> impl Iterator<Item = (usize, &Allocation)> {
+        self.allocations.iter().map(|(start, allocation)| (*start, allocation))
+    }
+
+    /// Records in the header of the allocation at `start` that it holds no pointers, so the garbage
+    /// collector does not look inside it.
+    pub fn set_pointer_free(&mut self, start: usize) {
+        let block = start - HEADER_SIZE;
+        self.set_word(block, self.word(block) | NO_POINTERS);
+    }
+
+    /// Frees every allocation that cannot be reached from `roots`, and returns the freed
+    /// allocations with their starts.
+    ///
+    /// Roots and the words of reachable allocations are treated as pointers if they point into an
+    /// allocation, whether they are pointers or not. Pointers in allocations are addresses of the
+    /// VM, `base` being the address of the first heap byte, and are only found at multiples of
+    /// four bytes from the start of the allocation.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = usize>, base: usize) -> Vec<(usize, Allocation)> {
+        let mut reachable = Vec::new();
+        for root in roots {
+            self.mark(root, &mut reachable);
+        }
+        while let Some(start) = reachable.pop() {
+            let size = self.allocations[&start].size;
+            for offset in (0..size.saturating_sub(3)).step_by(4) {
+                let word = u32::from_be_bytes(self.memory[start + offset..start + offset + 4].try_into().unwrap());
+                if let Some(address) = (word as usize).checked_sub(base) {
+                    self.mark(address, &mut reachable);
                 }
             }
         }
-        match best_fit {
-            Some(index) => {
-                let block = self.free_list.remove(index);
-                if block.size > size {
-                    self.free_list.push(FreeBlock {
-                        start: block.start + size,
-                        size: block.size - size,
-                    });
-                }
-                Ok(block.start)
+        let starts: Vec<usize> = self.allocations.keys().copied().collect();
+        let mut freed = Vec::new();
+        for start in starts {
+            let header = self.word(start - HEADER_SIZE);
+            if header & MARKED != 0 {
+                self.set_word(start - HEADER_SIZE, header & !MARKED);
+            } else {
+                freed.push((start, self.allocations[&start]));
+                self.release_allocation(start);
             }
-            None => Err("Out of memory".to_string()),
         }
+        freed
     }
 
-    pub fn free(&mut self, start: usize, size: usize) -> Result<(), String> {
-        if start + size > self.memory.len() {
-            return Err(format!("Cannot free memory at {} with size {}", start, size));
-        }
-        self.free_list.push(FreeBlock { start, size });
-        self.free_list.sort_by(|a, b| a.start.cmp(&b.start));
-        let mut index = 0;
-        while index < self.free_list.len() - 1 {
-            let current = &self.free_list[index];
-            let next = &self.free_list[index + 1];
-            if current.start + current.size == next.start {
-                self.free_list[index].size += next.size;
-                self.free_list.remove(index + 1);
+    /// Marks the allocation `address` points into, and adds it to `reachable` to be scanned if it is
+    /// newly marked and may hold pointers.
+    fn mark(&mut self, address: usize, reachable: &mut Vec<usize>) {
+        let Some((&start, allocation)) = self.allocations.range(..=address).next_back() else {
+            return;
+        };
+        // Pointers to empty allocations point at their start.
+        if address >= start + allocation.size.max(1) {
+            return;
+        }
+        let header = self.word(start - HEADER_SIZE);
+        if header & MARKED == 0 {
+            self.set_word(start - HEADER_SIZE, header | MARKED);
+            if header & NO_POINTERS == 0 {
+                reachable.push(start);
+            }
+        }
+    }
+
+    /// Resizes the allocation at `start` to `size` bytes, keeping as much of its content as fits,
+    /// and returns its new start. The allocation stays where it is if it shrinks or the block after
+    /// it is free and large enough, and is moved otherwise. The caller checks that `start` is a live
+    /// allocation.
+    pub fn reallocate(&mut self, start: usize, size: usize) -> Result<usize, String> {
+        let old_size = self.allocation_size(start).ok_or(format!("{} is not the start of an allocation", start))?;
+        let block = start - HEADER_SIZE;
+        let flags = self.word(block) & NO_POINTERS;
+        let current = self.tag(block).0;
+        let next = block + current;
+        let next_free = match next < self.memory.len() {
+            true => match self.tag(next) {
+                (next_size, false) => next_size,
+                _ => 0,
+            },
+            false => 0,
+        };
+        let needed = block_size(size).ok_or_else(out_of_memory)?;
+        if needed <= current + next_free {
+            if next_free > 0 {
+                self.remove_free(next, next_free);
+            }
+            let available = current + next_free;
+            if available - needed >= MIN_BLOCK_SIZE {
+                self.set_tags(block, needed, true);
+                self.release_block(block + needed, available - needed);
             } else {
-                index += 1;
+                self.set_tags(block, available, true);
             }
+            self.set_word(block, self.word(block) | flags);
+            self.allocations.insert(start, Allocation { size, site: self.site });
+            return Ok(start);
+        }
+        let new_start = self.allocate(size)?;
+        self.set_word(new_start - HEADER_SIZE, self.word(new_start - HEADER_SIZE) | flags);
+        self.set_word(new_start - TAG_SIZE, self.word(start - TAG_SIZE));
+        let kept = old_size.min(size);
+        self.memory.copy_within(start..start + kept, new_start);
+        self.release_allocation(start);
+        Ok(new_start)
+    }
+
+    pub fn allocation_count(&self) -> usize {
+        self.allocations.len()
+    }
+
+    /// The number of bytes in free blocks, including their tags.
+    pub fn free_bytes(&self) -> usize {
+        self.free_block_sizes().iter().sum()
+    }
+
+    /// The size of every free block, including its tags.
+    pub fn free_block_sizes(&self) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        for head in self.free_lists {
+            let mut block = head;
+            while block != NONE {
+                sizes.push(self.tag(block as usize).0);
+                block = self.next_free(block as usize);
+            }
+        }
+        sizes
+    }
+
+    /// Checks that the blocks, their boundary tags, the free lists and the tracked allocations
+    /// describe each other and lie within the memory, for heaps that were deserialized.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.limit > MAX_HEAP_SIZE || self.memory.len() > self.limit || !self.memory.len().is_multiple_of(ALIGNMENT) {
+            return Err(format!("heap of {} bytes does not fit its limit of {} bytes", self.memory.len(), self.limit));
+        }
+        let mut free_blocks = BTreeMap::new();
+        let mut allocated_blocks = BTreeMap::new();
+        let mut block = 0;
+        while block < self.memory.len() {
+            let (size, allocated) = match self.memory.len() - block >= MIN_BLOCK_SIZE {
+                true => self.tag(block),
+                false => (0, false),
+            };
+            if size < MIN_BLOCK_SIZE || !size.is_multiple_of(ALIGNMENT) || size > self.memory.len() - block {
+                return Err(format!("heap block at {} has an invalid size of {} bytes", block, size));
+            }
+            if self.tag(block + size - TAG_SIZE) != (size, allocated) {
+                return Err(format!("heap block at {} has a footer that does not match its header", block));
+            }
+            match allocated {
+                true => allocated_blocks.insert(block + HEADER_SIZE, size),
+                false => free_blocks.insert(block, size),
+            };
+            block += size;
+        }
+        for (start, allocation) in &self.allocations {
+            match allocated_blocks.remove(start) {
+                Some(size) if allocation.size <= size - HEADER_SIZE - TAG_SIZE => {}
+                _ => return Err(format!("heap allocation at {} does not match an allocated block", start)),
+            }
+        }
+        if let Some(start) = allocated_blocks.keys().next() {
+            return Err(format!("heap block at {} is allocated but not tracked", start - HEADER_SIZE));
+        }
+        for (class, head) in self.free_lists.iter().enumerate() {
+            let (mut block, mut previous) = (*head, NONE);
+            while block != NONE {
+                match free_blocks.remove(&(block as usize)) {
+                    Some(size) if size_class(size) == class && self.previous_free(block as usize) == previous => {}
+                    _ => return Err(format!("heap free list {} holds an invalid block at {}", class, block)),
+                }
+                previous = block;
+                block = self.next_free(block as usize);
+            }
+        }
+        if let Some(start) = free_blocks.keys().next() {
+            return Err(format!("free heap block at {} is not in a free list", start));
+        }
+        match self.freed.iter().find(|(start, freed)| start.checked_add(freed.size).is_none_or(|end| end > self.memory.len())) {
+            Some((start, _)) => Err(format!("freed heap allocation at {} lies outside the heap", start)),
+            None => Ok(()),
         }
-        Ok(())
     }
 
     pub fn load(&self, start: usize, size: usize) -> Result<&[u8], String> {
@@ -74,23 +401,25 @@ impl Heap {
         Ok(&self.memory[start..start + size])
     }
 
-
     pub fn store(&mut self, start: usize, data: &[u8]) -> Result<(), String> {
         if start + data.len() > self.memory.len() {
             return Err("Out of bounds".to_string());
         }
-        // check if is in free list
-        for block in self.free_list.iter() {
-            if start >= block.start && start + data.len() <= block.start + block.size {
-                return Err("Cannot store in free memory".to_string());
+        // Writes may use the padding of a block, but not its tags or other blocks.
+        let inside_allocation = match self.allocations.range(..=start).next_back() {
+            Some((&address, _)) => {
+                let block = address - HEADER_SIZE;
+                start + data.len() <= block + self.tag(block).0 - TAG_SIZE
             }
+            None => false,
+        };
+        if !inside_allocation {
+            return Err("Cannot store in free memory".to_string());
         }
         self.memory[start..start + data.len()].copy_from_slice(data);
         Ok(())
     }
 
-
-
     pub fn allocate_string(&mut self, string: &str) -> Result<usize, String> {
         let mut data = string.as_bytes().to_vec();
         data.push(0);
@@ -100,6 +429,148 @@ impl Heap {
     }
 
     pub fn print_state(&self) {
-        println!("Free list: {:?}", self.free_list);
+        println!("Allocations: {:?}", self.allocations);
+        println!("Free bytes: {}", self.free_bytes());
+    }
+
+    fn release_allocation(&mut self, start: usize) {
+        let allocation = self.allocations.remove(&start).unwrap();
+        let freed = FreedAllocation { size: allocation.size, site: allocation.site, freed_by: self.site };
+        self.freed.insert(start, freed);
+        let block = start - HEADER_SIZE;
+        let size = self.tag(block).0;
+        self.release_block(block, size);
+    }
+
+    /// The first free block of at least `size` bytes.
+    fn find_free(&self, size: usize) -> Option<usize> {
+        let class = size_class(size);
+        // Blocks in the class of the request may be too small, blocks in larger classes are not.
+        let mut block = self.free_lists[class];
+        while block != NONE {
+            if self.tag(block as usize).0 >= size {
+                return Some(block as usize);
+            }
+            block = self.next_free(block as usize);
+        }
+        self.free_lists[class + 1..].iter().find(|head| **head != NONE).map(|head| *head as usize)
+    }
+
+    /// Grows the memory so that a block of `size` bytes fits at its end, doubling it where possible,
+    /// and returns the start of the free block at the end.
+    fn grow(&mut self, size: usize) -> Result<usize, String> {
+        let old_size = self.memory.len();
+        // A free block at the end is merged with the new memory.
+        let trailing_free = match old_size.checked_sub(TAG_SIZE) {
+            Some(footer) => match self.tag(footer) {
+                (size, false) => size,
+                _ => 0,
+            },
+            None => 0,
+        };
+        let needed = (size - trailing_free).max(MIN_BLOCK_SIZE);
+        let required = old_size.checked_add(needed).ok_or_else(out_of_memory)?;
+        let new_size = align_down((old_size * 2).max(required).min(self.limit));
+        if new_size < required {
+            return Err(out_of_memory());
+        }
+        self.memory.resize(new_size, 0);
+        Ok(self.release_block(old_size, new_size - old_size))
     }
-}
\ No newline at end of file
+
+    /// Turns the block into a free block, merged with its free neighbours, and returns the start of
+    /// the merged block.
+    fn release_block(&mut self, mut start: usize, mut size: usize) -> usize {
+        let next = start + size;
+        if next < self.memory.len() {
+            let (next_size, allocated) = self.tag(next);
+            if !allocated {
+                self.remove_free(next, next_size);
+                size += next_size;
+            }
+        }
+        if start > 0 {
+            let (previous_size, allocated) = self.tag(start - TAG_SIZE);
+            if !allocated {
+                start -= previous_size;
+                self.remove_free(start, previous_size);
+                size += previous_size;
+            }
+        }
+        self.push_free(start, size);
+        start
+    }
+
+    fn tag(&self, offset: usize) -> (usize, bool) {
+        let tag = self.word(offset);
+        ((tag & !FLAGS) as usize, tag & ALLOCATED != 0)
+    }
+
+    fn set_tags(&mut self, start: usize, size: usize, allocated: bool) {
+        let tag = size as u32 | if allocated { ALLOCATED } else { 0 };
+        self.set_word(start, tag);
+        self.set_word(start + size - TAG_SIZE, tag);
+    }
+
+    fn next_free(&self, start: usize) -> u32 {
+        self.word(start + TAG_SIZE)
+    }
+
+    fn previous_free(&self, start: usize) -> u32 {
+        self.word(start + 2 * TAG_SIZE)
+    }
+
+    fn word(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.memory[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn set_word(&mut self, offset: usize, word: u32) {
+        self.memory[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    fn push_free(&mut self, start: usize, size: usize) {
+        self.set_tags(start, size, false);
+        let class = size_class(size);
+        let head = self.free_lists[class];
+        self.set_word(start + TAG_SIZE, head);
+        self.set_word(start + 2 * TAG_SIZE, NONE);
+        if head != NONE {
+            self.set_word(head as usize + 2 * TAG_SIZE, start as u32);
+        }
+        self.free_lists[class] = start as u32;
+    }
+
+    fn remove_free(&mut self, start: usize, size: usize) {
+        let next = self.next_free(start);
+        let previous = self.previous_free(start);
+        if previous == NONE {
+            self.free_lists[size_class(size)] = next;
+        } else {
+            self.set_word(previous as usize + TAG_SIZE, next);
+        }
+        if next != NONE {
+            self.set_word(next as usize + 2 * TAG_SIZE, previous);
+        }
+    }
+}
+
+/// The size of the block holding `size` bytes, including its tags, `None` if it does not fit in
+/// the address space.
+fn block_size(size: usize) -> Option<usize> {
+    let size = size.max(1).checked_add(HEADER_SIZE + TAG_SIZE + ALIGNMENT - 1)?;
+    Some(align_down(size).max(MIN_BLOCK_SIZE))
+}
+
+fn out_of_memory() -> String {
+    "Out of memory".to_string()
+}
+
+/// Classes hold blocks from one power of two up to the next, starting at `MIN_BLOCK_SIZE`.
+fn size_class(size: usize) -> usize {
+    let class = (usize::BITS - 1 - size.leading_zeros()) - MIN_BLOCK_SIZE.trailing_zeros();
+    (class as usize).min(SIZE_CLASSES - 1)
+}
+
+fn align_down(size: usize) -> usize {
+    size & !(ALIGNMENT - 1)
+}
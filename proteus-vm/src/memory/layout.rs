@@ -0,0 +1,70 @@
+//! The address space programs see.
+//!
+//! Every region starts at a fixed address, so compilers can rely on it no matter how the VM is
+//! configured:
+//!
+//! | Addresses                   | Region                                           |
+//! |-----------------------------|--------------------------------------------------|
+//! | `0x0000_0000..0x0000_1000`  | Null page, never mapped                          |
+//! | `0x0000_1000..0x1000_0000`  | Stack, `stack_size` bytes growing upwards        |
+//! | `0x1000_0000..0x2000_0000`  | Data, `data_size` zeroed bytes for globals       |
+//! | `0x2000_0000..0x8000_0000`  | Heap, growing upwards up to `max_heap_size`      |
+//!
+//! The part of each region past its configured size is a guard gap: accessing it, or the null page,
+//! is an error instead of landing in the next region. The stack and the data segment are capped so
+//! that at least one guard page always follows them, and the heap so that every address fits in a
+//! positive `i32`.
+
+pub const NULL_PAGE_SIZE: usize = 0x1000;
+pub const GUARD_SIZE: usize = 0x1000;
+pub const STACK_START: usize = NULL_PAGE_SIZE;
+pub const DATA_START: usize = 0x1000_0000;
+pub const HEAP_START: usize = 0x2000_0000;
+pub const HEAP_END: usize = 0x8000_0000;
+
+pub const MAX_STACK_SIZE: usize = DATA_START - GUARD_SIZE - STACK_START;
+pub const MAX_DATA_SIZE: usize = HEAP_START - GUARD_SIZE - DATA_START;
+pub const MAX_HEAP_SIZE: usize = HEAP_END - HEAP_START;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    NullPage,
+    Stack,
+    Data,
+    Heap,
+    /// Past the end of the address space.
+    Unmapped,
+}
+
+impl Region {
+    /// The region `address` falls in, whether or not it is mapped.
+    pub fn of(address: usize) -> Self {
+        match address {
+            _ if address < STACK_START => Region::NullPage,
+            _ if address < DATA_START => Region::Stack,
+            _ if address < HEAP_START => Region::Data,
+            _ if address < HEAP_END => Region::Heap,
+            _ => Region::Unmapped,
+        }
+    }
+
+    pub fn start(self) -> usize {
+        match self {
+            Region::NullPage => 0,
+            Region::Stack => STACK_START,
+            Region::Data => DATA_START,
+            Region::Heap => HEAP_START,
+            Region::Unmapped => HEAP_END,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::NullPage => "the null page",
+            Region::Stack => "the stack",
+            Region::Data => "the data segment",
+            Region::Heap => "the heap",
+            Region::Unmapped => "unmapped memory",
+        }
+    }
+}
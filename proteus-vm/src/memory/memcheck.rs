@@ -0,0 +1,150 @@
+//! Shadow memory for `--memcheck` runs.
+//!
+//! Every heap byte has a shadow state that follows it through allocation, the first write and
+//! free. Reads are only allowed from bytes that were written since they were allocated, writes only
+//! to allocated bytes, so use after free, reads of uninitialized memory and accesses outside of an
+//! allocation are reported at the instruction that makes them.
+
+use serde::{Deserialize, Serialize};
+
+use super::describe_site;
+use super::heap::Heap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum State {
+    /// Free memory that was never handed out, or the tags and padding of a block.
+    Unallocated,
+    Uninitialized,
+    Initialized,
+    Freed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Memcheck {
+    /// The address of the first heap byte, to report addresses the way the program sees them.
+    base: usize,
+    states: Vec<State>,
+}
+
+impl Memcheck {
+    pub fn new(base: usize) -> Self {
+        Self { base, states: Vec::new() }
+    }
+
+    pub fn allocated(&mut self, start: usize, size: usize) {
+        self.set(start, size, State::Uninitialized);
+    }
+
+    /// Marks the end of an allocation that shrank in place.
+    pub fn unallocated(&mut self, start: usize, size: usize) {
+        self.set(start, size, State::Unallocated);
+    }
+
+    pub fn freed(&mut self, start: usize, size: usize) {
+        self.set(start, size, State::Freed);
+    }
+
+    /// Carries the state of `size` bytes over to where a reallocation moved them.
+    pub fn moved(&mut self, from: usize, to: usize, size: usize) {
+        self.ensure(from + size);
+        self.ensure(to + size);
+        self.states.copy_within(from..from + size, to);
+    }
+
+    /// Checks that the shadow covers no more than the heap at `base`, and that every byte it
+    /// considers allocated lies in a live allocation, for shadows that were deserialized.
+    pub fn validate(&self, heap: &Heap, base: usize) -> Result<(), String> {
+        if self.base != base || self.states.len() > heap.memory.len() {
+            return Err("memcheck shadow does not match the heap".to_string());
+        }
+        let allocated = |index: &usize| matches!(self.states[*index], State::Uninitialized | State::Initialized);
+        match (0..self.states.len()).filter(allocated).find(|index| heap.allocation_containing(*index).is_none()) {
+            Some(index) => Err(format!("memcheck shadow marks heap byte {} as allocated outside any allocation", index)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_read(&self, heap: &Heap, start: usize, size: usize) -> Result<(), String> {
+        let Some(index) = (start..start + size).find(|index| self.state(*index) != State::Initialized) else {
+            return Ok(());
+        };
+        match self.state(index) {
+            State::Uninitialized => {
+                let (allocation_start, allocation) = heap.allocation_containing(index).unwrap();
+                Err(format!(
+                    "Read of uninitialized memory: reading {} bytes at {}, byte {} of the allocation at {} made by {} was never written",
+                    size,
+                    self.base + start,
+                    index - allocation_start,
+                    self.base + allocation_start,
+                    describe_site(allocation.site)
+                ))
+            }
+            _ => Err(self.describe_invalid_access(heap, "reading", start, size, index)),
+        }
+    }
+
+    /// Checks that `size` bytes at `start` may be written and marks them as initialized.
+    pub fn check_write(&mut self, heap: &Heap, start: usize, size: usize) -> Result<(), String> {
+        let invalid = (start..start + size).find(|index| {
+            !matches!(self.state(*index), State::Uninitialized | State::Initialized)
+        });
+        if let Some(index) = invalid {
+            return Err(self.describe_invalid_access(heap, "writing", start, size, index));
+        }
+        self.set(start, size, State::Initialized);
+        Ok(())
+    }
+
+    /// Describes an access of `size` bytes at `start` that touches the unallocated or freed byte at
+    /// `index`.
+    fn describe_invalid_access(&self, heap: &Heap, access: &str, start: usize, size: usize, index: usize) -> String {
+        if self.state(index) == State::Freed {
+            return match heap.freed_allocation_containing(index) {
+                Some((freed_start, freed)) => format!(
+                    "Use after free: {} {} bytes at {}, in the allocation at {} made by {} and freed by {}",
+                    access,
+                    size,
+                    self.base + start,
+                    self.base + freed_start,
+                    describe_site(freed.site),
+                    describe_site(freed.freed_by)
+                ),
+                None => format!("Use after free: {} {} bytes at {}", access, size, self.base + start),
+            };
+        }
+        match heap.allocation_containing(start) {
+            Some((allocation_start, allocation)) => format!(
+                "Out of bounds: {} {} bytes at {} overruns the {} byte allocation at {} made by {}",
+                access,
+                size,
+                self.base + start,
+                allocation.size,
+                self.base + allocation_start,
+                describe_site(allocation.site)
+            ),
+            None => format!(
+                "Out of bounds: {} {} bytes at {}, which is outside of any allocation",
+                access,
+                size,
+                self.base + start
+            ),
+        }
+    }
+
+    fn state(&self, index: usize) -> State {
+        self.states.get(index).copied().unwrap_or(State::Unallocated)
+    }
+
+    fn set(&mut self, start: usize, size: usize, state: State) {
+        self.ensure(start + size);
+        self.states[start..start + size].fill(state);
+    }
+
+    /// Makes room for the shadow of a heap that grew.
+    fn ensure(&mut self, size: usize) {
+        if self.states.len() < size {
+            self.states.resize(size, State::Unallocated);
+        }
+    }
+}
@@ -1,89 +1,308 @@
 use std::error::Error;
+use std::fmt;
 
+use serde::{Deserialize, Serialize};
+
+use crate::config::VmConfig;
+use gc::Collector;
+use heap::FreeError;
+use layout::{Region, HEAP_START, MAX_DATA_SIZE, MAX_STACK_SIZE, STACK_START};
+use memcheck::Memcheck;
+use snapshot::HeapSnapshot;
+use tags::TagStack;
+
+pub mod array;
+pub mod gc;
 pub mod heap;
+pub mod layout;
+pub mod memcheck;
+pub mod snapshot;
+pub mod stats;
+pub mod tags;
 
 #[repr(C)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Memory {
     pub heap: heap::Heap,
+    #[serde(with = "crate::utils::hex")]
     pub stack: Vec<u8>,
+    /// The address of the first free stack byte, see `layout`.
     pub stack_pointer: usize,
+    peak_stack_depth: usize,
+    /// Zeroed memory for globals at `layout::DATA_START`.
+    #[serde(with = "crate::utils::hex")]
+    pub data: Vec<u8>,
+    /// Shadow state of the heap, if accesses are checked.
+    pub memcheck: Option<Memcheck>,
+    /// The garbage collector, if enabled.
+    pub gc: Option<Collector>,
+    /// The width of every value on the stack, if pops are checked against it.
+    pub tags: Option<TagStack>,
+    /// The heap snapshots taken so far, see `take_snapshot`.
+    pub snapshots: Vec<HeapSnapshot>,
 }
 
-const HEAP_SIZE: usize = 1024 * 1024;
-const STACK_SIZE: usize = 8096;
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Memory {
     pub fn new() -> Self {
-        Self {
-            heap: heap::Heap::new(
-                HEAP_SIZE
-            ),
-            stack: vec![0; STACK_SIZE],
-            stack_pointer: 0,
+        Self::with_config(&VmConfig::default()).unwrap()
+    }
+
+    /// Fails if a size in `config` does not fit into its region.
+    pub fn with_config(config: &VmConfig) -> Result<Self, String> {
+        config.validate()?;
+        Ok(Self {
+            heap: heap::Heap::growable(config.heap_size, config.max_heap_size),
+            stack: vec![0; config.stack_size],
+            stack_pointer: STACK_START,
+            peak_stack_depth: 0,
+            data: vec![0; config.data_size],
+            memcheck: config.memcheck.then(|| Memcheck::new(HEAP_START)),
+            gc: config.gc_threshold.map(Collector::new),
+            tags: config.stack_tags.then(TagStack::new),
+            snapshots: Vec::new(),
+        })
+    }
+
+    /// Checks that the regions fit the address space and that the heap, its shadow and the stack
+    /// tags are consistent, for memory that was deserialized, e.g. from a checkpoint.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.stack.len() > MAX_STACK_SIZE || self.data.len() > MAX_DATA_SIZE {
+            return Err("the stack or the data segment exceeds its region".to_string());
+        }
+        if !(STACK_START..=self.stack_end()).contains(&self.stack_pointer) {
+            return Err(format!("stack pointer {} is outside the stack", self.stack_pointer));
+        }
+        self.heap.validate()?;
+        if let Some(memcheck) = &self.memcheck {
+            memcheck.validate(&self.heap, self.heap_start())?;
+        }
+        if let Some(tags) = &self.tags {
+            tags.validate(self.stack_depth())?;
         }
+        Ok(())
     }
 
     pub fn load(&self, address: usize, size: usize) -> Result<&[u8], String> {
-        if self.is_heap_address(address) {
-            let heap_address = address - self.heap_start();
-            self.heap.load(heap_address, size)
-        } else {
-            self.stack_load(address, size)
+        let (region, offset) = self.locate(address, size, "reading")?;
+        match region {
+            Region::Heap => {
+                if let Some(memcheck) = &self.memcheck {
+                    memcheck.check_read(&self.heap, offset, size)?;
+                }
+                self.heap.load(offset, size)
+            }
+            region => Ok(&self.segment(region)[offset..offset + size]),
         }
     }
 
-    fn stack_load(&self, address: usize, size: usize) -> Result<&[u8], String> {
-        if address + size > self.stack.len() {
-            Err(format!("SIGSEV: {} + {} > {}", address, size, self.stack_pointer))
-        } else {
-            Ok(&self.stack[address..address + size])
+    pub fn store(&mut self, address: usize, value: &[u8]) -> Result<(), String> {
+        let (region, offset) = self.locate(address, value.len(), "writing")?;
+        match region {
+            Region::Heap => {
+                if let Some(memcheck) = &mut self.memcheck {
+                    memcheck.check_write(&self.heap, offset, value.len())?;
+                }
+                self.heap.store(offset, value)
+            }
+            region => {
+                self.segment_mut(region)[offset..offset + value.len()].copy_from_slice(value);
+                Ok(())
+            }
         }
     }
 
-    pub fn store(&mut self, address: usize, value: &[u8]) -> Result<(), String> {
-        if self.is_heap_address(address) {
-            let heap_address = address - self.heap_start();
-            self.heap.store(heap_address, value)
-        } else {
-            self.stack_store(address, value)
+    /// Checks that `size` bytes at `address` could be stored, without storing anything.
+    pub fn check_store(&self, address: usize, size: usize) -> Result<(), String> {
+        self.locate(address, size, "writing").map(|_| ())
+    }
+
+    /// Finds the region holding `size` bytes at `address` and the offset of the first one in it,
+    /// rejecting accesses to the null page and to guard gaps.
+    fn locate(&self, address: usize, size: usize, access: &str) -> Result<(Region, usize), String> {
+        let region = Region::of(address);
+        match region {
+            Region::NullPage => {
+                return Err(format!("Null pointer dereference: {} {} bytes at {}", access, size, address));
+            }
+            Region::Unmapped => {
+                return Err(format!("Segmentation fault: {} {} bytes at {}, which is unmapped", access, size, address));
+            }
+            _ => {}
         }
+        let offset = address - region.start();
+        if offset.checked_add(size).is_none_or(|end| end > self.segment(region).len()) {
+            return Err(format!(
+                "Segmentation fault: {} {} bytes at {} runs past the end of {} into its guard gap",
+                access,
+                size,
+                address,
+                region.name()
+            ));
+        }
+        Ok((region, offset))
     }
 
-    fn stack_store(&mut self, address: usize, value: &[u8]) -> Result<(), String> {
-        if address + value.len() > self.stack.len() {
-            Err(format!("Stack overflow: {} + {} > {}", address, value.len(), self.stack.len()))
-        } else {
-            self.stack[address..address + value.len()].copy_from_slice(value);
-            Ok(())
+    fn segment(&self, region: Region) -> &[u8] {
+        match region {
+            Region::Stack => &self.stack,
+            Region::Data => &self.data,
+            Region::Heap => &self.heap.memory,
+            Region::NullPage | Region::Unmapped => &[],
+        }
+    }
+
+    fn segment_mut(&mut self, region: Region) -> &mut [u8] {
+        match region {
+            Region::Stack => &mut self.stack,
+            Region::Data => &mut self.data,
+            Region::Heap => &mut self.heap.memory,
+            Region::NullPage | Region::Unmapped => &mut [],
         }
     }
 
     pub fn allocate_heap(&mut self, size: usize) -> Result<usize, String> {
-        self.heap.allocate(size).map(
-            |address| {
-                address + self.heap_start()
+        if self.gc_due(size) {
+            self.collect_garbage();
+        }
+        let start = match self.heap.allocate(size) {
+            Err(_) if self.gc_active() => {
+                self.collect_garbage();
+                self.heap.allocate(size)?
+            }
+            result => result?,
+        };
+        self.gc_allocated(size);
+        if let Some(memcheck) = &mut self.memcheck {
+            memcheck.allocated(start, size);
+        }
+        Ok(start + self.heap_start())
+    }
+
+    /// Frees the heap allocation at `address` without knowing its size.
+    pub fn free_allocation(&mut self, address: usize) -> Result<(), String> {
+        if !self.is_heap_address(address) {
+            return Err(format!("{} is not a heap address", address));
+        }
+        let start = address - self.heap_start();
+        let size = self.heap.allocation_size(start);
+        self.heap
+            .free_allocation(start)
+            .map_err(|e| self.describe_free_error(address, e))?;
+        if let (Some(memcheck), Some(size)) = (&mut self.memcheck, size) {
+            memcheck.freed(start, size);
+        }
+        Ok(())
+    }
+
+    pub fn reallocate_heap(&mut self, address: usize, size: usize) -> Result<usize, String> {
+        if !self.is_heap_address(address) {
+            return Err(format!("{} is not a heap address", address));
+        }
+        let old_start = address - self.heap_start();
+        self.heap
+            .check_free(old_start, None)
+            .map_err(|e| self.describe_free_error(address, e))?;
+        let old_size = self.heap.allocation_size(old_start).unwrap();
+        let start = self.heap.reallocate(old_start, size)?;
+        if let Some(memcheck) = &mut self.memcheck {
+            if start == old_start && size >= old_size {
+                memcheck.allocated(start + old_size, size - old_size);
+            } else if start == old_start {
+                memcheck.unallocated(start + size, old_size - size);
+            } else {
+                memcheck.allocated(start, size);
+                memcheck.moved(old_start, start, old_size.min(size));
+                memcheck.freed(old_start, old_size);
             }
-        )
+        }
+        Ok(start + self.heap_start())
+    }
+
+    /// Adds a reference to the heap allocation at `address`.
+    pub fn retain(&mut self, address: usize) -> Result<(), String> {
+        if !self.is_heap_address(address) {
+            return Err(format!("{} is not a heap address", address));
+        }
+        self.heap
+            .retain(address - self.heap_start())
+            .map_err(|e| self.describe_free_error(address, e))?;
+        Ok(())
     }
 
-    pub fn move_stack_pointer_by(&mut self, offset: usize) {
+    /// Drops a reference to the heap allocation at `address`, freeing it when none remain.
+    pub fn release(&mut self, address: usize) -> Result<(), String> {
+        if !self.is_heap_address(address) {
+            return Err(format!("{} is not a heap address", address));
+        }
+        let start = address - self.heap_start();
+        let remaining = self.heap.release(start).map_err(|e| self.describe_free_error(address, e))?;
+        if remaining == 0 {
+            let size = self.heap.allocation_size(start).unwrap();
+            self.free_heap(address, size)?;
+        }
+        Ok(())
+    }
+
+    /// Copies `data` into a fresh heap allocation and returns its address.
+    pub fn allocate_heap_data(&mut self, data: &[u8]) -> Result<usize, String> {
+        let address = self.allocate_heap(data.len())?;
+        self.store(address, data)?;
+        Ok(address)
+    }
+
+    /// Reserves `offset` bytes on the stack without writing them.
+    pub fn move_stack_pointer_by(&mut self, offset: usize) -> Result<(), StackError> {
+        self.check_room(offset)?;
+        let depth = self.stack_depth();
+        if let Some(tags) = &mut self.tags {
+            tags.reserved(depth, offset);
+        }
         self.stack_pointer += offset;
+        self.peak_stack_depth = self.peak_stack_depth.max(depth + offset);
+        Ok(())
+    }
+
+    /// Counts a stack depth that code outside the interpreter, like compiled code, reached towards
+    /// the peak.
+    pub fn record_stack_depth(&mut self, depth: usize) {
+        self.peak_stack_depth = self.peak_stack_depth.max(depth);
     }
 
-    pub fn move_stack_pointer_to(&mut self, address: usize) {
-        if address > self.stack.len() {
-            panic!("Stack overflow: {} > {}", address, self.stack.len());
+    pub fn move_stack_pointer_to(&mut self, address: usize) -> Result<(), StackError> {
+        if address < self.stack_pointer {
+            self.pop(self.stack_pointer - address)?;
+            Ok(())
+        } else {
+            self.move_stack_pointer_by(address - self.stack_pointer)
         }
-        self.stack_pointer = address;
     }
 
-    pub fn push(&mut self, value: &[u8]) -> Result<usize, String> {
+    pub fn push(&mut self, value: &[u8]) -> Result<usize, StackError> {
+        self.check_room(value.len())?;
         let address = self.stack_pointer;
+        let index = self.stack_depth();
+        self.stack[index..index + value.len()].copy_from_slice(value);
+        if let Some(tags) = &mut self.tags {
+            tags.pushed(index, value.len());
+        }
         self.stack_pointer += value.len();
-        self.stack_store(address, value)?;
+        self.peak_stack_depth = self.peak_stack_depth.max(index + value.len());
         Ok(address)
     }
 
+    fn check_room(&self, size: usize) -> Result<(), StackError> {
+        if size > self.stack.len() - self.stack_depth() {
+            return Err(StackError::Overflow { size, depth: self.stack_depth(), capacity: self.stack.len() });
+        }
+        Ok(())
+    }
+
     pub fn push_string(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
         for byte in value.bytes() {
             self.push(&[byte])?;
@@ -92,29 +311,56 @@ impl Memory {
         Ok(())
     }
 
-    pub fn pop(&mut self, size: usize) -> Result<&[u8], String> {
+    /// Pops `size` bytes, which may hold several values.
+    pub fn pop(&mut self, size: usize) -> Result<&[u8], StackError> {
+        let index = self.top_index(0, size)?;
+        if let Some(tags) = &mut self.tags {
+            tags.popped(index, size)?;
+        }
         self.stack_pointer -= size;
-        let address = self.stack_pointer;
-        let value = self.stack_load(address, size)?;
-        Ok(value)
+        Ok(&self.stack[index..index + size])
+    }
+
+    /// Pops a single value of `size` bytes.
+    pub fn pop_value(&mut self, size: usize) -> Result<&[u8], StackError> {
+        if let Some(tags) = &self.tags {
+            tags.check_value(size)?;
+        }
+        self.pop(size)
     }
 
     pub fn pop_string(&mut self) -> Result<String, String> {
         let mut string = String::new();
-        let mut byte = self.pop(1)?[0];
+        let mut byte = self.pop_value(1)?[0];
         while byte != 0 {
             string.push(byte as char);
-            byte = self.pop(1)?[0];
+            byte = self.pop_value(1)?[0];
         }
         Ok(string)
     }
 
-    pub fn peek(&self, size: usize) -> Result<&[u8], String> {
-        self.stack_load(self.stack_pointer - size, size)
+    pub fn peek(&self, size: usize) -> Result<&[u8], StackError> {
+        self.peek_down(0, size)
     }
 
-    pub fn peek_down(&self, offset: usize, size: usize) -> Result<&[u8], String> {
-        self.stack_load(self.stack_pointer - offset - size, size)
+    /// Reads the single value of `size` bytes on top of the stack.
+    pub fn peek_value(&self, size: usize) -> Result<&[u8], StackError> {
+        if let Some(tags) = &self.tags {
+            tags.check_value(size)?;
+        }
+        self.peek(size)
+    }
+
+    pub fn peek_down(&self, offset: usize, size: usize) -> Result<&[u8], StackError> {
+        let index = self.top_index(offset, size)?;
+        Ok(&self.stack[index..index + size])
+    }
+
+    /// The stack index of the `size` bytes that lie `offset` bytes below the top of the stack.
+    fn top_index(&self, offset: usize, size: usize) -> Result<usize, StackError> {
+        self.stack_depth()
+            .checked_sub(offset + size)
+            .ok_or(StackError::Underflow { size: offset + size, depth: self.stack_depth() })
     }
 
     pub fn free(&mut self, address: usize, bytes: usize) -> Result<(), String> {
@@ -127,21 +373,50 @@ impl Memory {
 
     fn free_heap(&mut self, address: usize, bytes: usize) -> Result<(), String> {
         let heap_address = address - self.heap_start();
-        self.heap.free(heap_address, bytes)
+        self.heap
+            .free(heap_address, bytes)
+            .map_err(|e| self.describe_free_error(address, e))?;
+        if let Some(memcheck) = &mut self.memcheck {
+            memcheck.freed(heap_address, bytes);
+        }
+        Ok(())
     }
 
-    fn free_stack(&mut self, address: usize, bytes: usize) -> Result<(), String> {
-        if address + bytes > self.stack.len() {
-            Err(format!("SIGSEV: {} + {} > {}", address, bytes, self.stack.len()))
-        } else {
-            self.stack[address..address + bytes].iter_mut().for_each(|byte| *byte = 0);
-            Ok(())
+    fn describe_free_error(&self, address: usize, error: FreeError) -> String {
+        match error {
+            FreeError::DoubleFree { site, freed_by } => format!(
+                "Double free of {}, allocated by {} and already freed by {}",
+                address,
+                describe_site(site),
+                describe_site(freed_by)
+            ),
+            FreeError::SizeMismatch { size, allocation } => format!(
+                "Freeing {} bytes at {}, but it was allocated with {} bytes by {}",
+                size,
+                address,
+                allocation.size,
+                describe_site(allocation.site)
+            ),
+            FreeError::Interior { start, allocation } => format!(
+                "{} points {} bytes into the allocation at {} made by {}, not at its start",
+                address,
+                address - self.heap_start() - start,
+                start + self.heap_start(),
+                describe_site(allocation.site)
+            ),
+            FreeError::NotAllocated => format!("{} was never allocated", address),
         }
     }
 
+    /// Zeroes `bytes` bytes on the stack or in the data segment.
+    fn free_stack(&mut self, address: usize, bytes: usize) -> Result<(), String> {
+        let (region, offset) = self.locate(address, bytes, "freeing")?;
+        self.segment_mut(region)[offset..offset + bytes].fill(0);
+        Ok(())
+    }
 
     fn is_heap_address(&self, address: usize) -> bool {
-        address >= self.stack.len()
+        Region::of(address) == Region::Heap
     }
 
     /// Prints the stack frame in the following format:
@@ -156,9 +431,9 @@ impl Memory {
     /// It also prints 10 bytes beyond the stack pointer.
     pub fn stack_frame(&self) -> String {
         let mut frame = String::new();
-        let mut i = 0;
+        let mut i = STACK_START;
         while i < self.stack_pointer + 10 {
-            let value = self.stack.get(i).unwrap_or(&0);
+            let value = self.stack.get(i - STACK_START).unwrap_or(&0);
             frame.push_str(&format!("0x{:02x}: ", i));
             if i == self.stack_pointer {
                 frame.push_str(&format!("0x{:02x} <--- stack pointer)", value));
@@ -173,24 +448,90 @@ impl Memory {
 
     pub fn get_string(&self, start: usize) -> Result<String, String> {
         let mut string = String::new();
-        let mut index = if self.is_heap_address(start) {
-            start - self.heap_start()
-        } else {
-            start
-        };
-        let memory = if self.is_heap_address(start) {
-            &self.heap.memory
-        } else {
-            &self.stack
-        };
-        while memory[index] != 0 {
-            string.push(memory[index] as char);
+        let (region, mut index) = self.locate(start, 1, "reading")?;
+        let memory = self.segment(region);
+        loop {
+            if let (Some(memcheck), Region::Heap) = (&self.memcheck, region) {
+                memcheck.check_read(&self.heap, index, 1)?;
+            }
+            match memory.get(index) {
+                Some(0) => break,
+                Some(byte) => string.push(*byte as char),
+                None => return Err(format!("SIGSEV: Unterminated string at {}", start)),
+            }
             index += 1;
         }
         Ok(string)
     }
 
     pub fn heap_start(&self) -> usize {
-        self.stack.len()
+        HEAP_START
     }
-}
\ No newline at end of file
+
+    /// The address one past the last stack byte.
+    pub fn stack_end(&self) -> usize {
+        STACK_START + self.stack.len()
+    }
+
+    /// The number of bytes currently on the stack.
+    pub fn stack_depth(&self) -> usize {
+        self.stack_pointer - STACK_START
+    }
+}
+
+/// A stack operation that would leave the stack, or misread it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    /// `size` more bytes do not fit on a stack holding `depth` of its `capacity` bytes.
+    Overflow { size: usize, depth: usize, capacity: usize },
+    /// `size` bytes were taken from a stack holding only `depth` bytes.
+    Underflow { size: usize, depth: usize },
+    /// A value of `popped` bytes was taken where `site` pushed one of `pushed` bytes, see `tags`.
+    WidthMismatch { popped: usize, pushed: usize, site: Option<usize> },
+    /// Taking `popped` bytes would split the value of `pushed` bytes that `site` pushed.
+    SplitValue { popped: usize, pushed: usize, site: Option<usize> },
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackError::Overflow { size, depth, capacity } => write!(
+                f,
+                "Stack overflow: {} more bytes do not fit on the stack, which holds {} of {} bytes",
+                size, depth, capacity
+            ),
+            StackError::Underflow { size, depth } => {
+                write!(f, "Stack underflow: taking {} bytes from the stack, which holds {}", size, depth)
+            }
+            StackError::WidthMismatch { popped, pushed, site } => write!(
+                f,
+                "Width mismatch: popping a {} byte value, but the value on top of the stack has {} bytes and was pushed by {}",
+                popped,
+                pushed,
+                describe_site(*site)
+            ),
+            StackError::SplitValue { popped, pushed, site } => write!(
+                f,
+                "Width mismatch: popping {} bytes splits a {} byte value pushed by {}",
+                popped,
+                pushed,
+                describe_site(*site)
+            ),
+        }
+    }
+}
+
+impl Error for StackError {}
+
+impl From<StackError> for String {
+    fn from(error: StackError) -> Self {
+        error.to_string()
+    }
+}
+
+pub(crate) fn describe_site(site: Option<usize>) -> String {
+    match site {
+        Some(instruction) => format!("instruction {}", instruction),
+        None => "the host".to_string(),
+    }
+}
@@ -0,0 +1,150 @@
+//! Snapshots of the live heap allocations, to find out where a long-running program leaks.
+//!
+//! A snapshot records the address, size, allocation site and a hash of the contents of every live
+//! allocation. Snapshots are serialized to JSON, and `SnapshotDiff` compares two of them per
+//! allocation site.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::{describe_site, Memory};
+use crate::utils::fnv1a;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeapSnapshot {
+    pub label: String,
+    pub heap_size: usize,
+    pub allocations: Vec<SnapshotAllocation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotAllocation {
+    pub address: usize,
+    pub size: usize,
+    /// The instruction that made the allocation, `None` if the host made it.
+    pub site: Option<usize>,
+    /// The FNV-1a hash of the contents.
+    pub hash: u64,
+}
+
+/// The snapshots written by `--heap-snapshot`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub snapshots: Vec<HeapSnapshot>,
+}
+
+impl SnapshotFile {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid heap snapshot: {}", e))
+    }
+
+    /// The snapshot with the given label, or the last one without a label.
+    pub fn find(&self, label: Option<&str>) -> Option<&HeapSnapshot> {
+        match label {
+            Some(label) => self.snapshots.iter().find(|snapshot| snapshot.label == label),
+            None => self.snapshots.last(),
+        }
+    }
+}
+
+impl Memory {
+    pub fn snapshot(&self, label: &str) -> HeapSnapshot {
+        let allocations = self
+            .heap
+            .allocations()
+            .map(|(start, allocation)| SnapshotAllocation {
+                address: start + self.heap_start(),
+                size: allocation.size,
+                site: allocation.site,
+                hash: fnv1a(&self.heap.memory[start..start + allocation.size]),
+            })
+            .collect();
+        HeapSnapshot { label: label.to_string(), heap_size: self.heap.memory.len(), allocations }
+    }
+
+    /// Takes a snapshot and keeps it in `snapshots`.
+    pub fn take_snapshot(&mut self, label: &str) {
+        let snapshot = self.snapshot(label);
+        self.snapshots.push(snapshot);
+    }
+}
+
+/// How the live allocations of one site changed between two snapshots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SiteDiff {
+    pub site: Option<usize>,
+    pub allocations_before: usize,
+    pub allocations_after: usize,
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+impl SiteDiff {
+    pub fn allocation_growth(&self) -> isize {
+        self.allocations_after as isize - self.allocations_before as isize
+    }
+
+    pub fn byte_growth(&self) -> isize {
+        self.bytes_after as isize - self.bytes_before as isize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub before: String,
+    pub after: String,
+    /// The sites whose allocations changed, the ones that grew the most first.
+    pub sites: Vec<SiteDiff>,
+}
+
+impl SnapshotDiff {
+    pub fn new(before: &HeapSnapshot, after: &HeapSnapshot) -> Self {
+        let mut sites: BTreeMap<Option<usize>, SiteDiff> = BTreeMap::new();
+        for allocation in &before.allocations {
+            let site = sites.entry(allocation.site).or_insert(SiteDiff { site: allocation.site, ..SiteDiff::default() });
+            site.allocations_before += 1;
+            site.bytes_before += allocation.size;
+        }
+        for allocation in &after.allocations {
+            let site = sites.entry(allocation.site).or_insert(SiteDiff { site: allocation.site, ..SiteDiff::default() });
+            site.allocations_after += 1;
+            site.bytes_after += allocation.size;
+        }
+        let mut sites: Vec<SiteDiff> = sites
+            .into_values()
+            .filter(|site| site.allocation_growth() != 0 || site.byte_growth() != 0)
+            .collect();
+        sites.sort_by_key(|site| (-site.byte_growth(), -site.allocation_growth()));
+        Self { before: before.label.clone(), after: after.label.clone(), sites }
+    }
+
+    pub fn byte_growth(&self) -> isize {
+        self.sites.iter().map(SiteDiff::byte_growth).sum()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Heap changes from {:?} to {:?}: {:+} bytes", self.before, self.after, self.byte_growth())?;
+        for site in &self.sites {
+            write!(
+                f,
+                "\n  {}: {:+} allocations, {:+} bytes ({} to {} allocations, {} to {} bytes)",
+                describe_site(site.site),
+                site.allocation_growth(),
+                site.byte_growth(),
+                site.allocations_before,
+                site.allocations_after,
+                site.bytes_before,
+                site.bytes_after
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Usage statistics of the heap and the stack, for `--mem-stats` and embedders.
+
+use std::fmt;
+
+use super::Memory;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// The current size of the heap in bytes.
+    pub heap_size: usize,
+    /// The bytes requested by live allocations, without block headers and padding.
+    pub used_bytes: usize,
+    /// The bytes in free blocks, including their tags.
+    pub free_bytes: usize,
+    pub free_blocks: usize,
+    pub largest_free_block: usize,
+    pub live_allocations: usize,
+    pub stack_size: usize,
+    pub stack_depth: usize,
+    /// The deepest the stack has been, as seen by the interpreter.
+    pub peak_stack_depth: usize,
+}
+
+impl MemoryStats {
+    /// The share of free bytes outside of the largest free block: 0 when all free memory is in one
+    /// block, approaching 1 the more it is scattered over small ones.
+    pub fn fragmentation(&self) -> f64 {
+        if self.free_bytes == 0 {
+            return 0.0;
+        }
+        1.0 - self.largest_free_block as f64 / self.free_bytes as f64
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Heap: {} bytes, {} used by {} live allocations, {} free in {} blocks",
+            self.heap_size, self.used_bytes, self.live_allocations, self.free_bytes, self.free_blocks
+        )?;
+        writeln!(
+            f,
+            "Largest free block: {} bytes, fragmentation {:.1}%",
+            self.largest_free_block,
+            self.fragmentation() * 100.0
+        )?;
+        write!(
+            f,
+            "Stack: {} of {} bytes in use, peak {} bytes",
+            self.stack_depth, self.stack_size, self.peak_stack_depth
+        )
+    }
+}
+
+impl Memory {
+    pub fn stats(&self) -> MemoryStats {
+        let free_blocks = self.heap.free_block_sizes();
+        MemoryStats {
+            heap_size: self.heap.memory.len(),
+            used_bytes: self.heap.allocations().map(|(_, allocation)| allocation.size).sum(),
+            free_bytes: free_blocks.iter().sum(),
+            free_blocks: free_blocks.len(),
+            largest_free_block: free_blocks.iter().copied().max().unwrap_or(0),
+            live_allocations: self.heap.allocation_count(),
+            stack_size: self.stack.len(),
+            stack_depth: self.stack_depth(),
+            peak_stack_depth: self.peak_stack_depth,
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Shadow tags for the stack in `--stack-tags` runs.
+//!
+//! The stack itself is untyped bytes, so a program that pushes a byte and pops a word, or pops a
+//! string that was never pushed, silently reads whatever lies below. With tags enabled, every push
+//! records the width of the value and the instruction that pushed it. Popping a single value must
+//! then find a value of the same width on top, and popping several bytes at once must not split a
+//! value in two.
+
+use serde::{Deserialize, Serialize};
+
+use super::StackError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Kind {
+    Byte,
+    Word,
+    /// Untyped bytes, like a loaded block or the locals reserved by `alloc`, which may be popped in
+    /// parts.
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tag {
+    /// The stack depth the value was pushed at.
+    pub start: usize,
+    pub width: usize,
+    pub kind: Kind,
+    /// The instruction that pushed the value, `None` if the host pushed it.
+    pub site: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TagStack {
+    tags: Vec<Tag>,
+    site: Option<usize>,
+}
+
+impl TagStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attributes the following pushes to `site`.
+    pub fn set_site(&mut self, site: Option<usize>) {
+        self.site = site;
+    }
+
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    pub fn pushed(&mut self, start: usize, width: usize) {
+        let kind = match width {
+            1 => Kind::Byte,
+            4 => Kind::Word,
+            _ => Kind::Block,
+        };
+        self.push(start, width, kind);
+    }
+
+    pub fn reserved(&mut self, start: usize, width: usize) {
+        self.push(start, width, Kind::Block);
+    }
+
+    fn push(&mut self, start: usize, width: usize, kind: Kind) {
+        if width > 0 {
+            self.tags.push(Tag { start, width, kind, site: self.site });
+        }
+    }
+
+    /// Checks that the tags cover distinct parts of the stack up to `depth`, in order, for tags
+    /// that were deserialized.
+    pub fn validate(&self, depth: usize) -> Result<(), String> {
+        let mut end = 0;
+        for tag in &self.tags {
+            if tag.width == 0 || tag.start < end || tag.start.checked_add(tag.width).is_none_or(|tag_end| tag_end > depth) {
+                return Err(format!("stack tag at {} does not match the stack", tag.start));
+            }
+            end = tag.start + tag.width;
+        }
+        Ok(())
+    }
+
+    /// Checks that the top of the stack is a single value of `width` bytes, or untyped bytes.
+    pub fn check_value(&self, width: usize) -> Result<(), StackError> {
+        match self.tags.last() {
+            Some(tag) if tag.kind == Kind::Block && tag.width >= width => Ok(()),
+            Some(tag) if tag.width != width => {
+                Err(StackError::WidthMismatch { popped: width, pushed: tag.width, site: tag.site })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Drops the tags of everything above stack depth `depth`, failing if that splits a value. The
+    /// tags are left as they were when it fails, so they still describe the stack.
+    pub fn popped(&mut self, depth: usize, width: usize) -> Result<(), StackError> {
+        let kept = self.tags.iter().rposition(|tag| tag.start < depth).map_or(0, |index| index + 1);
+        if let Some(tag) = kept.checked_sub(1).map(|index| &mut self.tags[index]) {
+            if tag.start + tag.width > depth {
+                if tag.kind != Kind::Block {
+                    return Err(StackError::SplitValue { popped: width, pushed: tag.width, site: tag.site });
+                }
+                tag.width = depth - tag.start;
+            }
+        }
+        self.tags.truncate(kept);
+        Ok(())
+    }
+}
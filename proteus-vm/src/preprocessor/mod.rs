@@ -46,6 +46,13 @@ impl PreProcessor {
                 transpiled.push_str("
             ");
             }
+            // `.native name=library:symbol(types)->type` declares a native function for `ffcall`.
+            if let Some(declaration) = line.trim().strip_prefix(".native ") {
+                let declaration = declaration.trim();
+                let (name, _) = declaration.split_once('=').unwrap_or_else(|| panic!("Invalid native declaration: {}", declaration));
+                self.symbol_table.add_native(name.trim().to_string(), declaration.to_string());
+                continue;
+            }
             let (line, label) = &self.process_line(line);
             if line.is_empty() {
                 label_buffer.extend_from_slice(label);
@@ -70,8 +77,7 @@ impl PreProcessor {
         for line in lines {
             if !line.is_empty() {
                 new_content.push_str(line);
-                new_content.push_str("
-");
+                new_content.push('\n');
             }
         }
         self.content = new_content;
@@ -80,10 +86,7 @@ impl PreProcessor {
     fn process_line(&self, line: &str) -> (String, Vec<String>) {
         let tokens = self.tokenize_line(line);
         // only take tokens before ;
-        let tokens = tokens.into_iter().take_while(|token| match token {
-            Token::SemiColon => false,
-            _ => true,
-        }).collect::<Vec<Token>>();
+        let tokens = tokens.into_iter().take_while(|token| !matches!(token, Token::SemiColon)).collect::<Vec<Token>>();
         if tokens.is_empty() {
             return (String::new(), vec![]);
         }
@@ -99,7 +102,7 @@ impl PreProcessor {
         } else {
             return (String::new(), labels);
         };
-        let op_code: OpCode = OpCode::from_str(&op_code.to_uppercase()).expect(&*format!("Invalid opcode: {}", op_code));
+        let op_code: OpCode = OpCode::from_str(&op_code.to_uppercase()).unwrap_or_else(|_| panic!("Invalid opcode: {}", op_code));
 
         let operand = &tokens.get(current + 1);
 
@@ -185,13 +188,9 @@ impl PreProcessor {
     }
 
     fn parse_label(&self, tokens: &[Token]) -> Option<String> {
-        if let Some(Token::Identifier(label)) = &tokens.get(0) {
-            if let Some(token) = tokens.get(1) {
-                if let Token::Colon = token {
-                    Some(label.clone())
-                } else {
-                    None
-                }
+        if let Some(Token::Identifier(label)) = &tokens.first() {
+            if let Some(Token::Colon) = tokens.get(1) {
+                Some(label.clone())
             } else {
                 None
             }
@@ -209,7 +208,7 @@ impl PreProcessor {
                     let mut identifier = String::new();
                     identifier.push(c);
                     let mut colon = false;
-                    while let Some(c) = chars.next() {
+                    for c in chars.by_ref() {
                         match c {
                             'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
                                 identifier.push(c);
@@ -232,7 +231,7 @@ impl PreProcessor {
                     let mut number = String::new();
                     number.push(c);
                     let mut closed = false;
-                    while let Some(c) = chars.next() {
+                    for c in chars.by_ref() {
                         match c {
                             '0'..='9' => {
                                 number.push(c);
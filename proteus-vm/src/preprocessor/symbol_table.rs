@@ -1,16 +1,10 @@
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SymbolTable {
     symbols: HashMap<String, u32>,
-}
-
-impl Default for SymbolTable {
-    fn default() -> Self {
-        Self {
-            symbols: HashMap::new(),
-        }
-    }
+    /// The declaration of every native function by the name the program calls it with.
+    natives: HashMap<String, String>,
 }
 
 impl SymbolTable {
@@ -25,4 +19,12 @@ impl SymbolTable {
     pub fn get_symbol(&self, symbol: &str) -> Option<&u32> {
         self.symbols.get(symbol)
     }
+
+    pub fn add_native(&mut self, name: String, declaration: String) {
+        self.natives.insert(name, declaration);
+    }
+
+    pub fn get_native(&self, name: &str) -> Option<&str> {
+        self.natives.get(name).map(String::as_str)
+    }
 }
\ No newline at end of file
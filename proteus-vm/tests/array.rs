@@ -0,0 +1,36 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::memory::Memory;
+
+#[test]
+fn arrays_grow_as_elements_are_pushed() {
+    let byte_code = common::transpile(&common::program("array/list.pslb"));
+    let output = common::run(&byte_code, &["--memcheck"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Length, capacity after doubling from 2, the element at 7, the popped last element and the
+    // string built from two appends.
+    assert!(common::stable_stdout(&output).contains("100\n128\n49\n9801\nabcd"), "{}", common::stable_stdout(&output));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("all heap allocations were freed"));
+}
+
+#[test]
+fn arrays_survive_collections() {
+    let byte_code = common::transpile(&common::program("array/list.pslb"));
+    let output = common::run(&byte_code, &["--gc-threshold", "1"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(common::printed(&output), ["100", "128", "49", "9801", "abcd"]);
+}
+
+#[test]
+fn making_an_array_does_not_collect_its_elements() {
+    let config = VmConfig {
+        gc_threshold: Some(1),
+        ..VmConfig::default()
+    };
+    let mut memory = Memory::with_config(&config).unwrap();
+    let array = memory.array_new(4, 2).unwrap();
+    memory.array_push(array, &[0, 0, 0, 7, 0, 0, 0, 8, 0, 0, 0, 9]).unwrap();
+    assert_eq!(memory.array_get(array, 2).unwrap(), [0, 0, 0, 9]);
+    assert_eq!(memory.gc_stats().unwrap().objects_reclaimed, 0);
+}
@@ -0,0 +1,26 @@
+mod common;
+
+#[test]
+fn block_operations_work_across_stack_and_heap() {
+    let output = common::run(&common::transpile(&common::program("block/copy.pslb")), &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(common::stable_stdout(&output).contains("7\n1094795585\n0\n1"), "{}", common::stable_stdout(&output));
+}
+
+#[test]
+fn block_operations_check_the_whole_range() {
+    let output = common::run(&common::transpile(&common::program("block/overflow.pslb")), &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("opcode: MEMCPY"), "{}", stderr);
+    assert!(stderr.contains("Cannot store in free memory"), "{}", stderr);
+}
+
+#[test]
+fn memset_checks_the_range_before_filling_it() {
+    let output = common::run(&common::transpile(&common::program("block/huge_memset.pslb")), &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("opcode: MEMSET"), "{}", stderr);
+    assert!(stderr.contains("Segmentation fault: writing 2000000000 bytes at"), "{}", stderr);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use proteus_vm::evaluator::{Evaluator, Outcome};
+
+#[test]
+fn instruction_limit_stops_endless_loop() {
+    let byte_code = common::transpile(&common::program("budget/forever.pslb"));
+    for mode in [&[][..], &["--registers"][..]] {
+        let output = common::run(&byte_code, &[&["--max-instructions", "10000"], mode].concat());
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("instruction limit reached"));
+    }
+}
+
+#[test]
+fn timeout_stops_endless_loop() {
+    let byte_code = common::transpile(&common::program("budget/forever.pslb"));
+    for mode in [&[][..], &["--registers"][..]] {
+        let output = common::run(&byte_code, &[&["--timeout", "50"], mode].concat());
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("timeout reached"));
+    }
+}
+
+#[test]
+fn sufficient_budget_does_not_change_output() {
+    for program in common::programs() {
+        let byte_code = common::transpile(&program);
+        let unlimited = common::run(&byte_code, &[]);
+        let limited = common::run(&byte_code, &["--max-instructions", "100000000", "--timeout", "60000"]);
+        assert!(limited.status.success(), "{} ran out of budget", program.display());
+        assert_eq!(common::stable_stdout(&unlimited), common::stable_stdout(&limited));
+    }
+}
+
+#[test]
+fn more_fuel_continues_the_program() {
+    let byte_code = common::assemble(&common::program("budget/sum.pslb"));
+    let mut evaluator = Evaluator::new(&byte_code).unwrap();
+    evaluator.set_fuel(Some(1000));
+    assert_eq!(evaluator.evaluate().unwrap(), Outcome::OutOfFuel);
+    assert_eq!(evaluator.evaluate().unwrap(), Outcome::OutOfFuel);
+    let mut refills = 0;
+    loop {
+        evaluator.add_fuel(1000);
+        refills += 1;
+        if evaluator.evaluate().unwrap() == Outcome::Halted {
+            break;
+        }
+    }
+    assert!(refills > 1);
+    assert_eq!(evaluator.remove_top().unwrap(), 500500);
+
+    let mut unlimited = Evaluator::new(&byte_code).unwrap();
+    unlimited.add_fuel(10);
+    assert_eq!(unlimited.fuel(), Some(10));
+    assert_eq!(unlimited.evaluate().unwrap(), Outcome::OutOfFuel);
+}
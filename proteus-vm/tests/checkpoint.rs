@@ -0,0 +1,142 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::evaluator::{Checkpoint, Evaluator, Outcome};
+use proteus_vm::ffi::HostFunctions;
+use serde_json::{json, Value};
+
+#[test]
+fn resumed_program_prints_the_same_output() {
+    let byte_code = common::transpile(&common::program("checkpoint/squares.pslb"));
+    let directory = common::scratch_directory("checkpoint_resume");
+    let checkpoint = directory.join("checkpoint.json");
+    let checkpoint = checkpoint.to_str().unwrap();
+    for mode in [&[][..], &["--registers"][..]] {
+        let uninterrupted = common::run(&byte_code, mode);
+        assert!(uninterrupted.status.success());
+
+        let first = common::run(&byte_code, &[&["--max-instructions", "1500", "--checkpoint", checkpoint], mode].concat());
+        assert_eq!(first.status.code(), Some(2));
+        let second = common::run(
+            &byte_code,
+            &[&["--max-instructions", "1500", "--restore", checkpoint, "--checkpoint", checkpoint], mode].concat(),
+        );
+        assert_eq!(second.status.code(), Some(2));
+        let last = common::run(&byte_code, &[&["--restore", checkpoint], mode].concat());
+        assert!(last.status.success(), "{}", String::from_utf8_lossy(&last.stderr));
+
+        let resumed = [common::printed(&first), common::printed(&second), common::printed(&last)];
+        assert!(resumed.iter().all(|lines| !lines.is_empty()), "{:?}", resumed);
+        assert_eq!(resumed.concat(), common::printed(&uninterrupted));
+    }
+}
+
+#[test]
+fn checkpoint_restores_memory_and_allocator() {
+    let byte_code = common::assemble(&common::program("checkpoint/squares.pslb"));
+    let mut uninterrupted = Evaluator::new(&byte_code).unwrap();
+    assert_eq!(uninterrupted.evaluate().unwrap(), Outcome::Halted);
+
+    let mut paused = Evaluator::new(&byte_code).unwrap();
+    paused.set_fuel(Some(2000));
+    assert_eq!(paused.evaluate().unwrap(), Outcome::OutOfFuel);
+    let checkpoint = Checkpoint::from_json(&paused.checkpoint().to_json()).unwrap();
+    drop(paused);
+
+    let mut resumed = Evaluator::new(&byte_code).unwrap();
+    resumed.restore(checkpoint).unwrap();
+    assert_eq!(resumed.evaluate().unwrap(), Outcome::Halted);
+    assert_eq!(resumed.exit_code(), uninterrupted.exit_code());
+    assert_eq!(resumed.memory.stats(), uninterrupted.memory.stats());
+    assert_eq!(resumed.memory.heap.memory, uninterrupted.memory.heap.memory);
+    assert_eq!(resumed.memory.stack, uninterrupted.memory.stack);
+    assert_eq!(resumed.memory.allocate_heap(24), uninterrupted.memory.allocate_heap(24));
+}
+
+#[test]
+fn checkpoint_of_another_program_is_rejected() {
+    let squares = common::assemble(&common::program("checkpoint/squares.pslb"));
+    let other = common::assemble(&common::program("heap.pslb"));
+    let checkpoint = Evaluator::new(&squares).unwrap().checkpoint();
+    let mut evaluator = Evaluator::new(&other).unwrap();
+    assert!(evaluator.restore(checkpoint).unwrap_err().contains("different program"));
+    assert!(Checkpoint::from_json("{}").is_err());
+}
+
+#[test]
+fn checkpoints_outside_the_program_or_stack_are_rejected() {
+    let byte_code = common::assemble(&common::program("checkpoint/squares.pslb"));
+    let mut paused = Evaluator::new(&byte_code).unwrap();
+    paused.set_fuel(Some(2000));
+    assert_eq!(paused.evaluate().unwrap(), Outcome::OutOfFuel);
+    let checkpoint = paused.checkpoint();
+    let restore = |invalid: Checkpoint| Evaluator::new(&byte_code).unwrap().restore(invalid).unwrap_err();
+
+    let mut invalid = checkpoint.clone();
+    invalid.instruction_counter = 1_000_000;
+    assert!(restore(invalid).starts_with("Invalid checkpoint: instruction 1000000 is past the end"));
+    let mut invalid = checkpoint.clone();
+    invalid.memory.stack_pointer = 0;
+    assert_eq!(restore(invalid), "Invalid checkpoint: stack pointer 0 is outside the stack");
+    let mut invalid = checkpoint.clone();
+    invalid.stack_frames.clear();
+    assert_eq!(restore(invalid), "Invalid checkpoint: no stack frames");
+    let mut invalid = checkpoint.clone();
+    invalid.stack_frames.push(u32::MAX);
+    assert!(restore(invalid).ends_with("is outside the used stack"));
+
+    paused.restore(checkpoint).unwrap();
+    paused.set_fuel(None);
+    assert_eq!(paused.evaluate().unwrap(), Outcome::Halted);
+}
+
+/// Pauses the squares program and returns its checkpoint as JSON.
+fn paused_json(config: &VmConfig) -> Value {
+    let byte_code = common::assemble(&common::program("checkpoint/squares.pslb"));
+    let mut paused = Evaluator::with_config(&byte_code, HostFunctions::standard(), config).unwrap();
+    paused.set_fuel(Some(2000));
+    assert_eq!(paused.evaluate().unwrap(), Outcome::OutOfFuel);
+    serde_json::from_str(&paused.checkpoint().to_json()).unwrap()
+}
+
+fn restore_error(json: &Value, config: &VmConfig) -> String {
+    let byte_code = common::assemble(&common::program("checkpoint/squares.pslb"));
+    let checkpoint = Checkpoint::from_json(&json.to_string()).unwrap();
+    let mut evaluator = Evaluator::with_config(&byte_code, HostFunctions::standard(), config).unwrap();
+    evaluator.restore(checkpoint).unwrap_err()
+}
+
+#[test]
+fn checkpoints_with_a_tampered_heap_are_rejected() {
+    let config = VmConfig { memcheck: true, stack_tags: true, ..VmConfig::default() };
+    let json = paused_json(&config);
+    assert!(!json["memory"]["heap"]["allocations"].as_object().unwrap().is_empty());
+    let edits = [
+        ("free list", "/memory/heap/free_lists", json!(vec![4000000; 28])),
+        ("does not match an allocated block", "/memory/heap/allocations/999999", json!({"size": 4, "site": null})),
+        ("invalid size", "/memory/heap/memory", json!("0300000000000000")),
+        ("memcheck shadow does not match the heap", "/memory/memcheck/base", json!(0)),
+        ("stack tag", "/memory/tags/tags/0/start", json!(1000000)),
+    ];
+    for (message, pointer, value) in edits {
+        let mut tampered = json.clone();
+        let (parent, key) = pointer.rsplit_once('/').unwrap();
+        match tampered.pointer_mut(parent).unwrap() {
+            Value::Array(items) => items[key.parse::<usize>().unwrap()] = value,
+            object => object[key] = value,
+        }
+        let error = restore_error(&tampered, &config);
+        assert!(error.starts_with("Invalid checkpoint: ") && error.contains(message), "{}", error);
+    }
+
+    let byte_code = common::transpile(&common::program("checkpoint/squares.pslb"));
+    let directory = common::scratch_directory("checkpoint_tampered");
+    let path = directory.join("checkpoint.json");
+    let mut tampered = paused_json(&VmConfig::default());
+    tampered["memory"]["heap"]["free_lists"] = json!(vec![4000000; 28]);
+    std::fs::write(&path, tampered.to_string()).unwrap();
+    let output = common::run(&byte_code, &["--restore", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Could not restore") && stderr.contains("Invalid checkpoint: heap free list"), "{}", stderr);
+}
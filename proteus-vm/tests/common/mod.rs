@@ -0,0 +1,107 @@
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+pub fn programs() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("programs");
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "pslb"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+pub fn program(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("programs").join(name)
+}
+
+/// Transpiles the given assembly file and returns the path of the byte code.
+pub fn transpile(program: &Path) -> PathBuf {
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(program.with_extension("proteus").file_name().unwrap());
+    let status = Command::new(env!("CARGO_BIN_EXE_proteus-vm"))
+        .arg("transpile")
+        .arg(program)
+        .arg("-o")
+        .arg(&output)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "Could not transpile {}", program.display());
+    output
+}
+
+pub fn run(byte_code: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_proteus-vm"))
+        .arg("run")
+        .arg(byte_code)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The program output without lines that differ between runs, like the execution time.
+pub fn stable_stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with("Execution time"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The lines the program printed, without the ones the VM adds around them.
+pub fn printed(output: &Output) -> Vec<String> {
+    stable_stdout(output)
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("Running file"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Assembles the given file in process, for tests that drive the evaluator directly.
+pub fn assemble(program: &Path) -> Vec<u8> {
+    let code = std::fs::read_to_string(program).unwrap();
+    let (code, symbol_table) = proteus_vm::preprocessor::process(&code);
+    proteus_vm::loading::ByteCodeTranslator::new(&code, &symbol_table).translate()
+}
+
+pub fn run_with_input(byte_code: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_proteus-vm"))
+        .arg("run")
+        .arg(byte_code)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Runs the byte code with `directory` as the working directory.
+pub fn run_in(directory: &Path, byte_code: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_proteus-vm"))
+        .current_dir(directory)
+        .arg("run")
+        .arg(byte_code)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// A fresh, empty directory for the test with the given name.
+pub fn scratch_directory(name: &str) -> PathBuf {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Runs the `proteus-vm` subcommand `command` with the given arguments.
+pub fn command(command: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_proteus-vm")).arg(command).args(args).output().unwrap()
+}
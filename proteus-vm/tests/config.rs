@@ -0,0 +1,72 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::memory::heap::Heap;
+use proteus_vm::memory::layout::MAX_DATA_SIZE;
+use proteus_vm::memory::Memory;
+
+#[test]
+fn stack_size_limits_recursion_depth() {
+    let byte_code = common::transpile(&common::program("config/deep_recursion.pslb"));
+    let output = common::run(&byte_code, &[]);
+    assert!(output.status.success());
+    assert!(common::stable_stdout(&output).contains("2001000"));
+
+    let output = common::run(&byte_code, &["--stack-size", "8K"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Stack overflow"));
+}
+
+#[test]
+fn heap_grows_up_to_its_limit() {
+    let byte_code = common::transpile(&common::program("config/large_allocation.pslb"));
+    let output = common::run(&byte_code, &["--heap-size", "1M"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Out of memory"));
+
+    let output = common::run(&byte_code, &["--heap-size", "1M", "--max-heap-size", "4M"]);
+    assert!(output.status.success());
+    assert!(common::stable_stdout(&output).contains("ok"));
+}
+
+#[test]
+fn heap_too_small_for_a_block_grows_on_first_allocation() {
+    let mut heap = Heap::growable(8, 1024 * 1024);
+    assert!(heap.memory.is_empty());
+    assert!(heap.allocate(4).is_ok());
+    assert_eq!(heap.allocation_count(), 1);
+
+    let byte_code = common::transpile(&common::program("heap.pslb"));
+    let output = common::run(&byte_code, &["--heap-size", "8", "--max-heap-size", "1M"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(common::printed(&output), common::printed(&common::run(&byte_code, &[])));
+}
+
+#[test]
+fn oversized_and_negative_allocations_fail() {
+    let mut heap = Heap::growable(1024, 1024 * 1024);
+    assert_eq!(heap.allocate(usize::MAX).unwrap_err(), "Out of memory");
+    assert_eq!(heap.allocate(usize::MAX - 16).unwrap_err(), "Out of memory");
+
+    let byte_code = common::transpile(&common::program("mem/negative_size.pslb"));
+    let output = common::run(&byte_code, &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Negative size -1"));
+}
+
+#[test]
+fn sizes_larger_than_their_region_are_rejected() {
+    let byte_code = common::transpile(&common::program("heap.pslb"));
+    let output = common::run(&byte_code, &["--stack-size", "1G"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid configuration: Stack size 1073741824 exceeds the 268427264 bytes of the stack region"), "{}", stderr);
+
+    let output = common::run(&byte_code, &["--max-heap-size", "2G"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Maximum heap size 2147483648 exceeds"));
+
+    let config = VmConfig { data_size: MAX_DATA_SIZE + 1, ..VmConfig::default() };
+    assert!(matches!(Memory::with_config(&config), Err(e) if e.starts_with("Data size")));
+    assert!(Memory::with_config(&VmConfig { data_size: MAX_DATA_SIZE, ..config }).is_ok());
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use std::process::Command;
+
+fn run(args: &[&str]) -> String {
+    let byte_code = common::transpile(&common::program("env/args.pslb"));
+    let output = Command::new(env!("CARGO_BIN_EXE_proteus-vm"))
+        .env("PROTEUS_GREETING", "hello")
+        .arg("run")
+        .arg(&byte_code)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    common::stable_stdout(&output)
+}
+
+#[test]
+fn arguments_after_separator_are_passed_to_the_program() {
+    let stdout = run(&["--", "first", "--second"]);
+    assert!(stdout.contains("\n2\nfirst\n--second\nunset"), "unexpected output: {}", stdout);
+}
+
+#[test]
+fn environment_is_only_readable_when_allowed() {
+    assert!(run(&[]).contains("\n0\nunset"));
+    assert!(run(&["--allow-env"]).contains("\n0\nhello"));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use proteus_vm::evaluator::{Evaluator, Outcome};
+use proteus_vm::ffi::{FFIType, FFIValue, HostFunctions};
+use proteus_vm::loading::ByteCodeTranslator;
+use proteus_vm::preprocessor;
+
+#[test]
+fn unknown_imports_are_reported_before_running() {
+    let byte_code = common::transpile(&common::program("ffi/unknown_import.pslb"));
+    let output = common::run(&byte_code, &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown FFI functions: does_not_exist"));
+    // Nothing ran, so the println before the unknown call printed nothing.
+    assert!(common::printed(&output).is_empty());
+}
+
+#[test]
+fn returned_strings_and_buffers_are_heap_allocated() {
+    let byte_code = common::assemble(&common::program("ffi/returned_string.pslb"));
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let mut host_functions = HostFunctions::standard();
+    host_functions.register("greeting", vec![], FFIType::String, |_, _| Ok(FFIValue::String("hello".to_string())));
+    host_functions.register("buffer", vec![], FFIType::Bytes, |_, _| Ok(FFIValue::Bytes(vec![1, 2, 3])));
+    let record = received.clone();
+    host_functions.register("record", vec![FFIType::String], FFIType::Void, move |_, arguments| {
+        record.borrow_mut().push(format!("{:?}", arguments[0]));
+        Ok(FFIValue::Void)
+    });
+    let record = received.clone();
+    host_functions.register("record_bytes", vec![FFIType::Bytes], FFIType::Void, move |_, arguments| {
+        record.borrow_mut().push(format!("{:?}", arguments[0]));
+        Ok(FFIValue::Void)
+    });
+
+    let mut evaluator = Evaluator::with_host_functions(&byte_code, host_functions).unwrap();
+    assert_eq!(evaluator.evaluate().unwrap(), Outcome::Halted);
+    assert_eq!(*received.borrow(), ["String(\"hello\")", "Bytes([1, 2, 3])"]);
+    // Both allocations were freed by the program.
+    assert_eq!(evaluator.memory.heap.allocation_count(), 0);
+    assert_eq!(evaluator.memory.heap.free_bytes(), evaluator.memory.heap.memory.len());
+}
+
+#[test]
+fn return_values_must_match_the_declared_type() {
+    let (code, symbol_table) = preprocessor::process("ffcall name\nhalt\n");
+    let byte_code = ByteCodeTranslator::new(&code, &symbol_table).translate();
+
+    let mut host_functions = HostFunctions::standard();
+    host_functions.register("name", vec![], FFIType::String, |_, _| Ok(FFIValue::I32(7)));
+    let mut evaluator = Evaluator::with_host_functions(&byte_code, host_functions).unwrap();
+    let error = evaluator.evaluate().unwrap_err().to_string();
+    assert!(error.contains("Host function name returned I32(7) instead of String"), "{}", error);
+
+    let mut host_functions = HostFunctions::standard();
+    host_functions.register("name", vec![], FFIType::String, |_, _| Ok(FFIValue::Null));
+    let mut evaluator = Evaluator::with_host_functions(&byte_code, host_functions).unwrap();
+    assert_eq!(evaluator.evaluate().unwrap(), Outcome::Halted);
+    assert_eq!(evaluator.remove_top().unwrap(), 0);
+    assert_eq!(evaluator.memory.heap.allocation_count(), 0);
+}
+
+#[test]
+fn negative_buffer_lengths_are_rejected() {
+    let (code, symbol_table) = preprocessor::process("push -1\npush 268435456\nffcall record_bytes\nhalt\n");
+    let byte_code = ByteCodeTranslator::new(&code, &symbol_table).translate();
+    let mut host_functions = HostFunctions::standard();
+    host_functions.register("record_bytes", vec![FFIType::Bytes], FFIType::Void, |_, _| Ok(FFIValue::Void));
+    let mut evaluator = Evaluator::with_host_functions(&byte_code, host_functions).unwrap();
+    let error = evaluator.evaluate().unwrap_err().to_string();
+    assert!(error.contains("Negative size -1"), "{}", error);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::fs;
+
+#[test]
+fn files_are_only_accessible_in_allowed_directories() {
+    let byte_code = common::transpile(&common::program("fs/files.pslb"));
+    let directory = common::scratch_directory("fs");
+    fs::create_dir(directory.join("config")).unwrap();
+    fs::create_dir(directory.join("out")).unwrap();
+    fs::write(directory.join("config/settings.txt"), "verbose").unwrap();
+    fs::write(directory.join("config/users.txt"), "").unwrap();
+    fs::write(directory.join("secret.txt"), "secret").unwrap();
+
+    let output = common::run_in(
+        &directory,
+        &byte_code,
+        &["--allow-read", "config", "--allow-read", "out", "--allow-write", "out"],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        common::stable_stdout(&output).trim(),
+        [
+            "Running file: ".to_string() + byte_code.to_str().unwrap(),
+            "".to_string(),
+            "verbose\nsettings.txt\nusers.txt\n0\n3\n6\nreport\n0\n0\n-1".to_string(),
+        ]
+        .join("\n")
+    );
+    assert_eq!(fs::read_to_string(directory.join("out/report.txt")).unwrap(), "report");
+    assert!(!directory.join("config/other.txt").exists());
+}
+
+#[test]
+fn nothing_is_accessible_by_default() {
+    let byte_code = common::transpile(&common::program("fs/files.pslb"));
+    let directory = common::scratch_directory("fs_default");
+    fs::create_dir(directory.join("config")).unwrap();
+    fs::create_dir(directory.join("out")).unwrap();
+    fs::write(directory.join("config/settings.txt"), "verbose").unwrap();
+
+    let output = common::run_in(&directory, &byte_code, &[]);
+    assert!(output.status.success());
+    // read_file and list_dir return null, so nothing is printed for them, write_file and open
+    // fail with ERROR_NOT_ALLOWED and the reads and closes of the failed handle with
+    // ERROR_BAD_HANDLE.
+    assert_eq!(common::printed(&output), ["-1", "-1", "-3", "-3", "0", "-1"]);
+    assert!(!directory.join("out/report.txt").exists());
+}
+
+#[test]
+fn reads_are_checked_against_the_buffer_first() {
+    let byte_code = common::transpile(&common::program("fs/huge_read.pslb"));
+    let directory = common::scratch_directory("fs_huge_read");
+    fs::write(directory.join("in.txt"), "data").unwrap();
+
+    let output = common::run_in(&directory, &byte_code, &["--allow-read", "."]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("writing 2147483647 bytes at 268435456 runs past the end of the data segment"), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn symbolic_links_do_not_lead_out_of_the_sandbox() {
+    let byte_code = common::transpile(&common::program("fs/link.pslb"));
+    let directory = common::scratch_directory("fs_link");
+    fs::create_dir(directory.join("out")).unwrap();
+    std::os::unix::fs::symlink(directory.join("escaped.txt"), directory.join("out/link")).unwrap();
+
+    let output = common::run_in(&directory, &byte_code, &["--allow-write", "out"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(common::printed(&output), ["-1", "-1", "-1"]);
+    assert!(!directory.join("escaped.txt").exists());
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::evaluator::{Evaluator, Outcome};
+use proteus_vm::ffi::HostFunctions;
+
+#[test]
+fn unreachable_objects_are_collected() {
+    let byte_code = common::transpile(&common::program("gc/garbage.pslb"));
+    let output = common::run(&byte_code, &["--gc-threshold", "1K"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Both kept objects survive every collection.
+    assert!(common::stable_stdout(&output).contains("7\n42"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("reclaimed 1000 objects with 64000 bytes"), "{}", stderr);
+}
+
+#[test]
+fn collections_make_room_when_the_heap_is_full() {
+    let byte_code = common::transpile(&common::program("gc/garbage.pslb"));
+    let output = common::run(&byte_code, &["--heap-size", "16K"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Out of memory"));
+
+    let output = common::run(&byte_code, &["--heap-size", "16K", "--gc"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(common::stable_stdout(&output).contains("7\n42"));
+}
+
+#[test]
+fn gc_statistics_are_available_to_embedders() {
+    let byte_code = common::assemble(&common::program("gc/garbage.pslb"));
+    let config = VmConfig {
+        gc_threshold: Some(4096),
+        ..VmConfig::default()
+    };
+    let mut evaluator = Evaluator::with_config(&byte_code, HostFunctions::standard(), &config).unwrap();
+    assert_eq!(evaluator.evaluate().unwrap(), Outcome::Halted);
+    let stats = evaluator.memory.gc_stats().unwrap();
+    // One collection every 64 objects and the one at the end.
+    assert_eq!(stats.collections, 1000 / 64 + 1);
+    assert_eq!(stats.objects_reclaimed, 1000);
+    assert_eq!(stats.bytes_reclaimed, 64000);
+    assert_eq!(evaluator.memory.heap.allocation_count(), 2);
+}
+
+#[test]
+fn host_functions_do_not_collect_their_own_allocations() {
+    let byte_code = common::transpile(&common::program("gc/arrays.pslb"));
+    let output = common::run(&byte_code, &["--gc-threshold", "20"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(common::printed(&output), ["20", "19", "xxxxxxxxxxxxxxxxxxxx"]);
+}
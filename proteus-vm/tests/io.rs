@@ -0,0 +1,43 @@
+mod common;
+
+#[test]
+fn console_input_output_and_exit_code() {
+    let byte_code = common::transpile(&common::program("io/echo.pslb"));
+    for mode in [&[][..], &["--registers"][..]] {
+        let output = common::run_with_input(&byte_code, mode, "hello world\n41\n");
+        assert_eq!(output.status.code(), Some(3));
+        let stdout = common::stable_stdout(&output);
+        assert!(stdout.contains("> hello world\n42"), "unexpected output: {}", stdout);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "ok");
+    }
+}
+
+#[test]
+fn read_int_rejects_invalid_input() {
+    let byte_code = common::transpile(&common::program("io/echo.pslb"));
+    let output = common::run_with_input(&byte_code, &[], "line\nforty-one\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not an integer"));
+}
+
+#[test]
+fn exit_code_is_kept_when_stepping() {
+    let byte_code = common::transpile(&common::program("io/exit.pslb"));
+    for mode in [&[][..], &["-s"][..]] {
+        let output = common::run_with_input(&byte_code, mode, "");
+        assert_eq!(output.status.code(), Some(3));
+    }
+}
+
+#[test]
+fn byte_code_without_an_import_table_calls_println_as_function_0() {
+    let byte_code = common::assemble(&common::program("io/legacy.pslb"));
+    // The import table holds only println: the magic, the count, the length and the name.
+    let header = 4 + 4 + 4 + "println".len();
+    assert_eq!(&byte_code[header - 7..header], b"println");
+    let legacy = common::scratch_directory("io_legacy").join("legacy.proteus");
+    std::fs::write(&legacy, &byte_code[header..]).unwrap();
+    let output = common::run(&legacy, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(common::printed(&output), ["hi", "ho"]);
+}
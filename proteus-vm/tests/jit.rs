@@ -0,0 +1,46 @@
+#![cfg(feature = "jit")]
+
+mod common;
+
+use proteus_vm::evaluator::{Evaluator, Outcome};
+
+#[test]
+fn compiled_code_matches_interpreter() {
+    for program in common::programs() {
+        let byte_code = common::transpile(&program);
+        let interpreted = common::run(&byte_code, &[]);
+        let compiled = common::run(&byte_code, &["--jit", "--jit-threshold", "1"]);
+        assert!(interpreted.status.success(), "{} failed in the interpreter", program.display());
+        assert!(compiled.status.success(), "{} failed when compiled", program.display());
+        assert_eq!(
+            common::stable_stdout(&interpreted),
+            common::stable_stdout(&compiled),
+            "{} behaves differently when compiled",
+            program.display()
+        );
+    }
+}
+
+#[test]
+fn hot_functions_are_compiled() {
+    for name in ["fibonacci.pslb", "sum_loop.pslb"] {
+        let byte_code = common::assemble(&common::program(name));
+        let mut evaluator = Evaluator::new(&byte_code).unwrap();
+        evaluator.enable_jit(1);
+        assert_eq!(evaluator.evaluate().unwrap(), Outcome::Halted);
+        assert!(evaluator.jit().unwrap().compiled_functions() > 0, "nothing in {} was compiled", name);
+    }
+}
+
+#[test]
+fn compiled_code_counts_towards_the_peak_stack_depth() {
+    for name in ["fibonacci.pslb", "sum_loop.pslb", "gcd.pslb"] {
+        let byte_code = common::assemble(&common::program(name));
+        let mut interpreted = Evaluator::new(&byte_code).unwrap();
+        assert_eq!(interpreted.evaluate().unwrap(), Outcome::Halted);
+        let mut compiled = Evaluator::new(&byte_code).unwrap();
+        compiled.enable_jit(1);
+        assert_eq!(compiled.evaluate().unwrap(), Outcome::Halted);
+        assert_eq!(compiled.memory.stats().peak_stack_depth, interpreted.memory.stats().peak_stack_depth, "{}", name);
+    }
+}
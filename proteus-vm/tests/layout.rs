@@ -0,0 +1,55 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::memory::layout::{Region, DATA_START, HEAP_START, STACK_START};
+use proteus_vm::memory::Memory;
+
+#[test]
+fn regions_start_at_fixed_addresses() {
+    assert_eq!(Region::of(0), Region::NullPage);
+    assert_eq!(Region::of(STACK_START), Region::Stack);
+    assert_eq!(Region::of(DATA_START), Region::Data);
+    assert_eq!(Region::of(HEAP_START), Region::Heap);
+
+    let mut memory = Memory::new();
+    assert_eq!(memory.stack_pointer, STACK_START);
+    assert_eq!(memory.push(&[1, 2, 3, 4]).unwrap(), STACK_START);
+    let allocation = memory.allocate_heap(4).unwrap();
+    assert_eq!(Region::of(allocation), Region::Heap);
+}
+
+#[test]
+fn null_dereferences_trap() {
+    let mut memory = Memory::new();
+    assert_eq!(memory.load(0, 4).unwrap_err(), "Null pointer dereference: reading 4 bytes at 0");
+    assert_eq!(memory.store(8, &[1]).unwrap_err(), "Null pointer dereference: writing 1 bytes at 8");
+    assert!(memory.get_string(0).is_err());
+
+    let output = common::run(&common::transpile(&common::program("mem/null.pslb")), &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Null pointer dereference: reading 4 bytes at 0"));
+}
+
+#[test]
+fn accesses_past_a_region_hit_its_guard_gap() {
+    let config = VmConfig { stack_size: 1024, data_size: 1024, ..VmConfig::default() };
+    let mut memory = Memory::with_config(&config).unwrap();
+    let error = memory.load(STACK_START + 1022, 4).unwrap_err();
+    assert!(error.contains("runs past the end of the stack into its guard gap"), "{}", error);
+    let error = memory.store(DATA_START + 1024, &[1]).unwrap_err();
+    assert!(error.contains("runs past the end of the data segment into its guard gap"), "{}", error);
+    let error = memory.load(HEAP_START + memory.heap.memory.len(), 1).unwrap_err();
+    assert!(error.contains("runs past the end of the heap into its guard gap"), "{}", error);
+    let error = memory.load(DATA_START + 4, usize::MAX).unwrap_err();
+    assert!(error.contains("runs past the end of the data segment"), "{}", error);
+}
+
+#[test]
+fn the_data_segment_holds_globals() {
+    let mut memory = Memory::new();
+    assert_eq!(memory.load(DATA_START, 4).unwrap(), &[0; 4]);
+    memory.store(DATA_START + 4, &[1, 2, 3, 4]).unwrap();
+    assert_eq!(memory.load(DATA_START + 4, 4).unwrap(), &[1, 2, 3, 4]);
+    memory.free(DATA_START + 4, 4).unwrap();
+    assert_eq!(memory.load(DATA_START + 4, 4).unwrap(), &[0; 4]);
+}
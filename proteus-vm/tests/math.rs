@@ -0,0 +1,25 @@
+mod common;
+
+fn numbers(output: &std::process::Output) -> Vec<i32> {
+    common::stable_stdout(output).lines().filter_map(|line| line.parse().ok()).collect()
+}
+
+#[test]
+fn math_functions() {
+    let byte_code = common::transpile(&common::program("math/math.pslb"));
+    let output = common::run(&byte_code, &["--seed", "42"]);
+    assert!(output.status.success());
+    let numbers = numbers(&output);
+    assert_eq!(numbers[..5], [5, 3, 7, 1024, 7]);
+    assert!(numbers[5..].iter().all(|number| (1..100).contains(number)));
+}
+
+#[test]
+fn random_is_reproducible_with_a_seed() {
+    let byte_code = common::transpile(&common::program("math/math.pslb"));
+    let first = numbers(&common::run(&byte_code, &["--seed", "42"]));
+    let second = numbers(&common::run(&byte_code, &["--seed", "42"]));
+    let other = numbers(&common::run(&byte_code, &["--seed", "43"]));
+    assert_eq!(first, second);
+    assert_ne!(first[5..], other[5..]);
+}
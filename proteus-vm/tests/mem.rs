@@ -0,0 +1,78 @@
+mod common;
+
+use proteus_vm::evaluator::{Evaluator, Outcome};
+use proteus_vm::memory::heap::Heap;
+use proteus_vm::memory::Memory;
+
+#[test]
+fn malloc_realloc_calloc_and_free() {
+    let program = common::program("mem/malloc.pslb");
+    let output = common::run(&common::transpile(&program), &[]);
+    assert!(output.status.success());
+    assert!(common::stable_stdout(&output).contains("16\n0"));
+
+    let byte_code = common::assemble(&program);
+    let mut evaluator = Evaluator::new(&byte_code).unwrap();
+    assert_eq!(evaluator.evaluate().unwrap(), Outcome::Halted);
+    let heap = &evaluator.memory.heap;
+    assert_eq!(heap.allocation_count(), 0);
+    assert_eq!(heap.free_bytes(), heap.memory.len());
+}
+
+fn failure(program: &str) -> String {
+    let output = common::run(&common::transpile(&common::program(program)), &[]);
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn invalid_frees_name_the_allocation_site() {
+    // Instruction 3 is the halloc in every program.
+    let stderr = failure("mem/double_free.pslb");
+    assert!(stderr.contains("allocated by instruction 3 and already freed by instruction 6"), "{}", stderr);
+
+    let stderr = failure("mem/size_mismatch.pslb");
+    assert!(stderr.contains("Freeing 4 bytes at"), "{}", stderr);
+    assert!(stderr.contains("but it was allocated with 8 bytes by instruction 3"), "{}", stderr);
+
+    let stderr = failure("mem/interior_free.pslb");
+    assert!(stderr.contains("points 4 bytes into the allocation at"), "{}", stderr);
+    assert!(stderr.contains("made by instruction 3, not at its start"), "{}", stderr);
+}
+
+#[test]
+fn freeing_memory_that_was_never_allocated_fails() {
+    let mut memory = Memory::new();
+    let address = memory.heap_start() + 100;
+    assert_eq!(memory.free_allocation(address).unwrap_err(), format!("{} was never allocated", address));
+
+    let allocation = memory.allocate_heap(8).unwrap();
+    memory.free_allocation(allocation).unwrap();
+    let error = memory.free_allocation(allocation).unwrap_err();
+    assert!(error.contains("allocated by the host and already freed by the host"), "{}", error);
+}
+
+#[test]
+fn hrealloc_keeps_the_content() {
+    let output = common::run(&common::transpile(&common::program("mem/hrealloc.pslb")), &["--memcheck"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(common::stable_stdout(&output).contains("33"));
+}
+
+#[test]
+fn reallocation_grows_in_place_when_possible() {
+    let mut heap = Heap::new(4096);
+    let start = heap.allocate(8).unwrap();
+    heap.store(start, &[1; 8]).unwrap();
+    // Nothing follows the allocation, so it grows in place.
+    assert_eq!(heap.reallocate(start, 100).unwrap(), start);
+    let blocker = heap.allocate(8).unwrap();
+    let moved = heap.reallocate(start, 1000).unwrap();
+    assert_ne!(moved, start);
+    assert_eq!(heap.load(moved, 8).unwrap(), &[1; 8]);
+    // Shrinking never moves.
+    assert_eq!(heap.reallocate(moved, 10).unwrap(), moved);
+    heap.free_allocation(moved).unwrap();
+    heap.free_allocation(blocker).unwrap();
+    assert_eq!(heap.free_bytes(), heap.memory.len());
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::process::Output;
+
+fn run_memcheck(program: &str) -> Output {
+    common::run(&common::transpile(&common::program(program)), &["--memcheck"])
+}
+
+fn failure(program: &str) -> String {
+    let output = run_memcheck(program);
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn invalid_heap_accesses_are_trapped() {
+    // Instruction 3 is the halloc in every program.
+    let stderr = failure("memcheck/use_after_free.pslb");
+    assert!(stderr.contains("Use after free: reading 4 bytes at 536870920"), "{}", stderr);
+    assert!(stderr.contains("made by instruction 3 and freed by instruction 10"), "{}", stderr);
+
+    let stderr = failure("memcheck/uninitialized.pslb");
+    assert!(stderr.contains("Read of uninitialized memory: reading 4 bytes at 536870924"), "{}", stderr);
+    assert!(stderr.contains("byte 4 of the allocation at 536870920 made by instruction 3 was never written"), "{}", stderr);
+
+    let stderr = failure("memcheck/overrun.pslb");
+    assert!(stderr.contains("Out of bounds: writing 4 bytes at 536870926 overruns the 8 byte allocation"), "{}", stderr);
+}
+
+#[test]
+fn leaks_are_reported_at_halt() {
+    let output = run_memcheck("memcheck/leak.pslb");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Leak report: 2 allocations with 40 bytes were not freed"), "{}", stderr);
+    assert!(stderr.contains("16 bytes at 536870920, allocated by instruction 2 in the function at instruction 2"), "{}", stderr);
+    assert!(stderr.contains("24 bytes at"), "{}", stderr);
+    assert!(stderr.contains("allocated by instruction 8 in the function at instruction 7"), "{}", stderr);
+}
+
+#[test]
+fn correct_programs_pass_the_memcheck() {
+    for program in ["strings.pslb", "mem/malloc.pslb"] {
+        let output = run_memcheck(program);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("all heap allocations were freed"));
+    }
+}
@@ -0,0 +1,109 @@
+#![cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+
+mod common;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+const LIBRARY_SOURCE: &str = r#"
+#include <stdio.h>
+
+int add(int a, int b) {
+    return a + b;
+}
+
+const char *greet(const char *name) {
+    static char buffer[64];
+    snprintf(buffer, sizeof buffer, "hello, %s", name);
+    return buffer;
+}
+
+int format_number(char *buffer, int size, int value) {
+    return snprintf(buffer, size, "number %d", value);
+}
+
+long long add_one(long long value) {
+    return value + 1;
+}
+
+int sum_bytes(const unsigned char *bytes, int length) {
+    int sum = 0;
+    for (int i = 0; i < length; i++) {
+        sum += bytes[i];
+    }
+    return sum;
+}
+"#;
+
+fn build_library(test: &str) -> PathBuf {
+    let directory = common::scratch_directory(test);
+    let source = directory.join("sample.c");
+    let library = directory.join("libsample.so");
+    std::fs::write(&source, LIBRARY_SOURCE).unwrap();
+    let status = Command::new("cc")
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(&library)
+        .arg(&source)
+        .status()
+        .unwrap();
+    assert!(status.success(), "Could not build the sample library");
+    library
+}
+
+#[test]
+fn calls_native_functions() {
+    let library = build_library("native_calls");
+    let library = library.to_str().unwrap();
+    let byte_code = common::transpile(&common::program("native/calls.pslb"));
+    let output = common::run(
+        &byte_code,
+        &[
+            "--native",
+            &format!("add={}(i32,i32)->i32", library),
+            "--native",
+            &format!("greet={}(string)->string", library),
+            "--native",
+            &format!("sum_bytes={}(bytes)->i32", library),
+            "--native",
+            "c_abs=libc.so.6:abs(i32)->i32",
+        ],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(common::stable_stdout(&output).contains("42\nhello, vm\n6\n7"));
+}
+
+#[test]
+fn missing_symbols_are_reported_before_running() {
+    let library = build_library("native_missing_symbol");
+    let byte_code = common::transpile(&common::program("native/calls.pslb"));
+    let output = common::run(&byte_code, &["--native", &format!("add={}:missing(i32,i32)->i32", library.display())]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Could not load native function add"));
+}
+
+#[test]
+fn programs_declare_native_functions_the_host_allows() {
+    let library = build_library("native_declared");
+    let directory = library.parent().unwrap();
+    let byte_code = common::transpile(&common::program("native/declared.pslb"));
+
+    let output = common::run_in(directory, &byte_code, &["--allow-native"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // format_number wrote into the buffer it was given.
+    assert_eq!(common::printed(&output), ["42", "9", "number 42"]);
+
+    let output = common::run_in(directory, &byte_code, &[]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Native function add=./libsample.so(i32,i32)->i32 is not allowed"), "{}", stderr);
+}
+
+#[test]
+fn i64_values_keep_both_words() {
+    let library = build_library("native_i64");
+    let byte_code = common::transpile(&common::program("native/wide.pslb"));
+    let output = common::run(&byte_code, &["--native", &format!("add_one={}(i64)->i64", library.display())]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // 0x1_80000000 + 1 as the low and the high word, then -2 + 1.
+    assert_eq!(common::printed(&output), ["-2147483647", "1", "-1", "-1"]);
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use proteus_vm::memory::Memory;
+
+#[test]
+fn objects_are_freed_when_the_last_reference_is_released() {
+    let byte_code = common::transpile(&common::program("refcount/shared.pslb"));
+    let output = common::run(&byte_code, &["--refcount-debug"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(common::printed(&output), ["5"]);
+    // Only the object that was never released is left.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Refcount report: 1 object is still referenced"), "{}", stderr);
+    assert!(stderr.contains("4 bytes at"), "{}", stderr);
+    assert!(stderr.contains("with 1 reference, allocated by instruction 12 in the function at instruction 2"), "{}", stderr);
+}
+
+#[test]
+fn releasing_a_freed_object_fails() {
+    let mut memory = Memory::new();
+    let address = memory.allocate_heap(8).unwrap();
+    memory.retain(address).unwrap();
+    assert_eq!(memory.heap.reference_count(address - memory.heap_start()), Some(2));
+    memory.release(address).unwrap();
+    memory.release(address).unwrap();
+    assert_eq!(memory.heap.allocation_count(), 0);
+    let error = memory.release(address).unwrap_err();
+    assert!(error.starts_with(&format!("Double free of {}", address)), "{}", error);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use proteus_vm::evaluator::{Evaluator, VmError};
+use proteus_vm::ir;
+
+#[test]
+fn register_ir_matches_stack_interpreter() {
+    for program in common::programs() {
+        let byte_code = common::transpile(&program);
+        let stack = common::run(&byte_code, &[]);
+        let registers = common::run(&byte_code, &["--registers"]);
+        assert!(stack.status.success(), "{} failed in the stack interpreter", program.display());
+        assert_eq!(
+            common::stable_stdout(&stack),
+            common::stable_stdout(&registers),
+            "{} behaves differently on the register IR",
+            program.display()
+        );
+    }
+}
+
+#[test]
+fn arithmetic_wraps_around_on_both_interpreters() {
+    let byte_code = common::transpile(&common::program("registers/overflow.pslb"));
+    for mode in [&[][..], &["--registers"][..]] {
+        let output = common::run(&byte_code, mode);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(common::printed(&output), ["-2147483648", "0"]);
+    }
+}
+
+#[test]
+fn faults_are_reported_at_the_same_instruction() {
+    for name in ["registers/division_by_zero.pslb", "registers/bad_load.pslb", "stack/underflow.pslb"] {
+        let byte_code = common::transpile(&common::program(name));
+        let stack = common::run(&byte_code, &[]);
+        let registers = common::run(&byte_code, &["--registers"]);
+        assert!(!stack.status.success() && !registers.status.success(), "{}", name);
+        assert_eq!(String::from_utf8_lossy(&stack.stderr), String::from_utf8_lossy(&registers.stderr), "{}", name);
+    }
+
+    let byte_code = common::assemble(&common::program("registers/division_by_zero.pslb"));
+    let mut evaluator = Evaluator::new(&byte_code).unwrap();
+    let program = ir::translate(&evaluator.byte_code_parser).unwrap();
+    let error = evaluator.evaluate_registers(&program).unwrap_err();
+    let error = error.downcast_ref::<VmError>().unwrap_or_else(|| panic!("{}", error));
+    assert!(matches!(error, VmError::DivisionByZero { instruction: 5, call_depth: 1, .. }), "{}", error);
+    assert_eq!(error.to_string(), "Division by zero (instruction 5, call depth 1)");
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use proteus_vm::evaluator::Evaluator;
+use proteus_vm::memory::snapshot::{SnapshotDiff, SnapshotFile};
+use proteus_vm::memory::Memory;
+
+#[test]
+fn snapshots_record_live_allocations() {
+    let mut memory = Memory::new();
+    let kept = memory.allocate_heap_data(&[1, 2, 3, 4]).unwrap();
+    let freed = memory.allocate_heap(8).unwrap();
+    memory.take_snapshot("before");
+    memory.free_allocation(freed).unwrap();
+    let same = memory.allocate_heap_data(&[1, 2, 3, 4]).unwrap();
+    memory.take_snapshot("after");
+
+    let before = &memory.snapshots[0];
+    assert_eq!(before.allocations.len(), 2);
+    assert_eq!((before.allocations[0].address, before.allocations[0].size), (kept, 4));
+    assert_eq!(before.allocations[0].site, None);
+    let after = &memory.snapshots[1];
+    let hash = |address| after.allocations.iter().find(|allocation| allocation.address == address).unwrap().hash;
+    assert_eq!(hash(kept), hash(same));
+
+    let file = SnapshotFile { snapshots: memory.snapshots.clone() };
+    let file = SnapshotFile::from_json(&file.to_json()).unwrap();
+    assert_eq!(file.snapshots, memory.snapshots);
+    assert_eq!(file.find(None).unwrap().label, "after");
+    assert!(SnapshotFile::from_json("{}").is_err());
+
+    let diff = SnapshotDiff::new(file.find(Some("before")).unwrap(), file.find(Some("after")).unwrap());
+    assert_eq!(diff.sites.len(), 1);
+    assert_eq!((diff.sites[0].allocations_before, diff.sites[0].allocations_after), (2, 2));
+    assert_eq!(diff.byte_growth(), -4);
+}
+
+#[test]
+fn programs_take_snapshots_that_can_be_diffed() {
+    let program = common::program("snapshot/growth.pslb");
+    let byte_code = common::assemble(&program);
+    let mut evaluator = Evaluator::new(&byte_code).unwrap();
+    evaluator.evaluate().unwrap();
+    let labels: Vec<&str> = evaluator.memory.snapshots.iter().map(|snapshot| snapshot.label.as_str()).collect();
+    assert_eq!(labels, ["a", "b"]);
+
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("growth.json");
+    let path = path.to_str().unwrap();
+    let output = common::run(&common::transpile(&program), &["--heap-snapshot", path]);
+    assert!(output.status.success());
+    let file = SnapshotFile::from_json(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(file.snapshots.len(), 3);
+    assert_eq!(file.find(None).unwrap().label, "exit");
+
+    let output = common::command("diff-snapshots", &[&format!("{}:a", path), &format!("{}:b", path)]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Heap changes from \"a\" to \"b\": +160 bytes"), "{}", stdout);
+    assert!(stdout.contains("instruction 16: +10 allocations, +160 bytes (0 to 10 allocations, 0 to 160 bytes)"), "{}", stdout);
+    assert!(!stdout.contains("instruction 4"), "{}", stdout);
+}
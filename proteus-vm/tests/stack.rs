@@ -0,0 +1,68 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::evaluator::{Evaluator, VmError};
+use proteus_vm::ffi::HostFunctions;
+use proteus_vm::memory::{Memory, StackError};
+
+fn vm_error(program: &str, config: &VmConfig) -> VmError {
+    let byte_code = common::assemble(&common::program(program));
+    let mut evaluator = Evaluator::with_config(&byte_code, HostFunctions::standard(), config).unwrap();
+    let error = evaluator.evaluate().unwrap_err();
+    error.downcast_ref::<VmError>().unwrap_or_else(|| panic!("{}", error)).clone()
+}
+
+#[test]
+fn popping_an_empty_stack_underflows() {
+    let error = vm_error("stack/underflow.pslb", &VmConfig::default());
+    let VmError::StackUnderflow { instruction, call_depth, .. } = error else {
+        panic!("{}", error);
+    };
+    assert_eq!((instruction, call_depth), (3, 1));
+
+    let output = common::run(&common::transpile(&common::program("stack/underflow.pslb")), &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Stack underflow: taking 4 bytes from the stack, which holds 0 (instruction 3, call depth 1)"), "{}", stderr);
+}
+
+#[test]
+fn deep_recursion_overflows() {
+    let config = VmConfig { stack_size: 8 * 1024, ..VmConfig::default() };
+    let error = vm_error("config/deep_recursion.pslb", &config);
+    let VmError::StackOverflow { call_depth, message, .. } = &error else {
+        panic!("{}", error);
+    };
+    assert!(*call_depth > 100, "{}", error);
+    assert!(message.contains("do not fit on the stack, which holds 8192 of 8192 bytes"), "{}", error);
+}
+
+#[test]
+fn calls_can_be_limited_in_depth() {
+    let config = VmConfig { max_call_depth: Some(100), ..VmConfig::default() };
+    let error = vm_error("config/deep_recursion.pslb", &config);
+    assert!(matches!(error, VmError::StackOverflow { call_depth: 100, .. }), "{}", error);
+    assert!(error.to_string().contains("calls are limited to a depth of 100"));
+
+    let byte_code = common::transpile(&common::program("config/deep_recursion.pslb"));
+    let output = common::run(&byte_code, &["--max-call-depth", "100"]);
+    assert!(!output.status.success());
+    let output = common::run(&byte_code, &["--max-call-depth", "5000"]);
+    assert!(output.status.success());
+    assert!(common::stable_stdout(&output).contains("2001000"));
+}
+
+#[test]
+fn stack_operations_are_checked() {
+    let config = VmConfig { stack_size: 8, ..VmConfig::default() };
+    let mut memory = Memory::with_config(&config).unwrap();
+    assert_eq!(memory.pop(1).unwrap_err(), StackError::Underflow { size: 1, depth: 0 });
+    assert_eq!(memory.peek_down(4, 1).unwrap_err(), StackError::Underflow { size: 5, depth: 0 });
+    memory.push(&[1; 6]).unwrap();
+    assert_eq!(memory.push(&[2; 4]).unwrap_err(), StackError::Overflow { size: 4, depth: 6, capacity: 8 });
+    assert_eq!(memory.stack_depth(), 6);
+    assert!(memory.move_stack_pointer_by(3).is_err());
+    assert!(memory.move_stack_pointer_to(memory.stack_pointer - 7).is_err());
+    memory.move_stack_pointer_to(memory.stack_pointer - 6).unwrap();
+    assert_eq!(memory.stack_depth(), 0);
+}
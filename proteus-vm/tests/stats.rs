@@ -0,0 +1,51 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::memory::Memory;
+
+#[test]
+fn stats_track_heap_usage_and_fragmentation() {
+    let config = VmConfig { heap_size: 4096, max_heap_size: 4096, ..VmConfig::default() };
+    let mut memory = Memory::with_config(&config).unwrap();
+    let stats = memory.stats();
+    assert_eq!(stats.heap_size, 4096);
+    assert_eq!((stats.used_bytes, stats.live_allocations, stats.free_blocks), (0, 0, 1));
+    assert_eq!(stats.largest_free_block, stats.free_bytes);
+    assert_eq!(stats.fragmentation(), 0.0);
+
+    let first = memory.allocate_heap(100).unwrap();
+    let second = memory.allocate_heap(20).unwrap();
+    memory.allocate_heap(30).unwrap();
+    let stats = memory.stats();
+    assert_eq!((stats.used_bytes, stats.live_allocations), (150, 3));
+
+    // Freeing the first allocation leaves a hole the rest of the free memory is not adjacent to.
+    memory.free_allocation(first).unwrap();
+    let stats = memory.stats();
+    assert_eq!((stats.used_bytes, stats.live_allocations, stats.free_blocks), (50, 2, 2));
+    assert!(stats.largest_free_block < stats.free_bytes);
+    assert!(stats.fragmentation() > 0.0 && stats.fragmentation() < 0.1);
+
+    memory.free_allocation(second).unwrap();
+    assert_eq!(memory.stats().free_blocks, 2);
+}
+
+#[test]
+fn stats_track_the_peak_stack_depth() {
+    let mut memory = Memory::new();
+    memory.push(&[0; 12]).unwrap();
+    memory.move_stack_pointer_by(20).unwrap();
+    memory.pop(30).unwrap();
+    let stats = memory.stats();
+    assert_eq!((stats.stack_depth, stats.peak_stack_depth, stats.stack_size), (2, 32, 64 * 1024));
+}
+
+#[test]
+fn mem_stats_are_printed_at_exit() {
+    let output = common::run(&common::transpile(&common::program("memcheck/leak.pslb")), &["--mem-stats"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Heap: 1048576 bytes, 40 used by 2 live allocations"), "{}", stderr);
+    assert!(stderr.contains("Stack: 0 of 65536 bytes in use, peak 12 bytes"), "{}", stderr);
+    assert!(stderr.contains("24 bytes at 536870952, allocated by instruction 8 in the function at instruction 7"), "{}", stderr);
+}
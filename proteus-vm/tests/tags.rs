@@ -0,0 +1,75 @@
+mod common;
+
+use proteus_vm::config::VmConfig;
+use proteus_vm::evaluator::{Evaluator, VmError};
+use proteus_vm::ffi::HostFunctions;
+use proteus_vm::memory::{Memory, StackError};
+
+fn tagged_failure(program: &str) -> String {
+    let byte_code = common::transpile(&common::program(program));
+    assert!(common::run(&byte_code, &[]).status.success());
+    let output = common::run(&byte_code, &["--stack-tags"]);
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn popping_bytes_as_an_int_is_reported() {
+    let stderr = tagged_failure("tags/byte_as_int.pslb");
+    assert!(
+        stderr.contains("popping a 4 byte value, but the value on top of the stack has 1 bytes and was pushed by instruction 6 (instruction 7, call depth 1)"),
+        "{}",
+        stderr
+    );
+
+    let byte_code = common::assemble(&common::program("tags/byte_as_int.pslb"));
+    let config = VmConfig { stack_tags: true, ..VmConfig::default() };
+    let mut evaluator = Evaluator::with_config(&byte_code, HostFunctions::standard(), &config).unwrap();
+    let error = evaluator.evaluate().unwrap_err();
+    assert!(matches!(error.downcast_ref::<VmError>(), Some(VmError::WidthMismatch { instruction: 7, .. })), "{}", error);
+}
+
+#[test]
+fn popping_part_of_a_value_is_reported() {
+    let stderr = tagged_failure("tags/split.pslb");
+    assert!(stderr.contains("popping 4 bytes splits a 4 byte value pushed by instruction 3"), "{}", stderr);
+}
+
+#[test]
+fn correct_programs_pass_the_check() {
+    for program in common::programs() {
+        let byte_code = common::transpile(&program);
+        let unchecked = common::run(&byte_code, &[]);
+        let checked = common::run(&byte_code, &["--stack-tags"]);
+        assert!(checked.status.success(), "{}: {}", program.display(), String::from_utf8_lossy(&checked.stderr));
+        assert_eq!(common::stable_stdout(&unchecked), common::stable_stdout(&checked));
+    }
+}
+
+#[test]
+fn values_can_be_popped_together_or_from_untyped_blocks() {
+    let config = VmConfig { stack_tags: true, ..VmConfig::default() };
+    let mut memory = Memory::with_config(&config).unwrap();
+    memory.push(&[0; 4]).unwrap();
+    memory.push(&[1]).unwrap();
+    memory.push(&[2]).unwrap();
+    assert_eq!(memory.pop(6).unwrap(), &[0, 0, 0, 0, 1, 2]);
+
+    memory.push(&[0; 4]).unwrap();
+    assert!(matches!(memory.pop_value(1), Err(StackError::WidthMismatch { popped: 1, pushed: 4, site: None })));
+    memory.push(&[1]).unwrap();
+    let tags = memory.tags.as_ref().unwrap().tags().to_vec();
+    assert!(matches!(memory.pop(3), Err(StackError::SplitValue { popped: 3, pushed: 4, .. })));
+    // The failed pop left the byte above the word and its tag in place.
+    assert_eq!(memory.tags.as_ref().unwrap().tags(), tags);
+    assert_eq!(memory.pop_value(1).unwrap(), &[1]);
+    assert!(matches!(memory.pop(2), Err(StackError::SplitValue { popped: 2, pushed: 4, .. })));
+
+    memory.push(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    memory.move_stack_pointer_by(8).unwrap();
+    assert!(memory.pop_value(4).is_ok());
+    assert!(memory.pop_value(1).is_ok());
+    assert!(memory.pop(3).is_ok());
+    assert_eq!(memory.pop_value(4).unwrap(), &[5, 6, 7, 8]);
+    assert_eq!(memory.pop_string().unwrap_err(), "Width mismatch: popping a 1 byte value, but the value on top of the stack has 4 bytes and was pushed by the host");
+}